@@ -0,0 +1,178 @@
+use pulldown_cmark::{Event, Tag, html};
+use std::borrow::Cow;
+
+use parser::SidenoteParser;
+use sidenotes::start_margin_note;
+use sidenote_error::SidenoteError;
+
+
+/// the attributes that may follow an image, `![caption](image.png){.fullwidth}`
+#[derive(Debug, PartialEq)]
+enum FigureStyle {
+    Normal,
+    Fullwidth,
+    Margin
+}
+
+
+impl FigureStyle {
+    fn from_attribute(text: &str) -> Option<FigureStyle> {
+        match text.trim() {
+            "" => Some(FigureStyle::Normal),
+            "{.fullwidth}" => Some(FigureStyle::Fullwidth),
+            "{.margin}" => Some(FigureStyle::Margin),
+            _ => None
+        }
+    }
+}
+
+
+/// the parts of a figure read from the markdown: the image's URL and
+/// title, the events of its caption and the style
+type RawFigure<'a> = (Cow<'a, str>, Cow<'a, str>, Vec<(Event<'a>, usize, usize)>, FigureStyle);
+
+
+/// An image on its own in a paragraph is a figure, captioned with its alt
+/// text. Following tufte-css, `{.fullwidth}` after the image spans the
+/// figure across the margin, and `{.margin}` places it in the margin like
+/// a margin note.
+impl<'a> SidenoteParser<'a> {
+
+    /// called at the start of a paragraph: read ahead to check whether it
+    /// contains only an image. If not, hand back the events to parse them
+    /// as usual.
+    pub fn parse_figure(&mut self) -> Result<Option<Event<'a>>, SidenoteError> {
+        let mut read_ahead = vec![];
+        match self.read_figure(&mut read_ahead) {
+            Some((url, title, caption, style)) => {
+                let url = self.substitute_variables(url)?;
+                let caption = self.parse_caption(caption, &url, &title)?;
+                Ok(Some(self.render_figure(url, title, caption, style)))
+            },
+            None => {
+                read_ahead.reverse();
+                self.lookahead.extend(read_ahead);
+                Ok(None)
+            }
+        }
+    }
+
+    /// parse the caption like any other text, for its math, variables and
+    /// escapes. It ends where the image does. The caption is also the alt
+    /// text of the image, so it can't have sidenotes.
+    fn parse_caption(&mut self, caption: Vec<(Event<'a>, usize, usize)>, url: &Cow<'a, str>,
+                     title: &Cow<'a, str>) -> Result<Vec<Event<'a>>, SidenoteError> {
+        let end = caption.last().map_or(self.span.1, |e| e.2);
+        self.lookahead.push((Event::End(Tag::Image(url.clone(), title.clone())), end, end));
+        self.lookahead.extend(caption.into_iter().rev());
+        let mut parsed = vec![];
+        while let Some(event) = self.next_parsed() {
+            let event = event?;
+            if self.in_sidenote_block {
+                self.in_sidenote_block = false;
+                return Err(SidenoteError::InCaption(self.source_position(self.sidenote_start)));
+            }
+            match event {
+                Event::End(Tag::Image(_, _)) => break,
+                e => parsed.push(e)
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn read_figure(&mut self, read_ahead: &mut Vec<(Event<'a>, usize, usize)>)
+        -> Option<RawFigure<'a>> {
+        let (url, title) = match self.next_raw_event() {
+            Some((Event::Start(Tag::Image(url, title)), start, end)) => {
+                read_ahead.push((Event::Start(Tag::Image(url.clone(), title.clone())), start, end));
+                (url, title)
+            },
+            Some(event) => {
+                read_ahead.push(event);
+                return None;
+            },
+            None => {
+                return None;
+            }
+        };
+
+        let mut caption = vec![];
+        loop {
+            let event = self.next_raw_event()?;
+            read_ahead.push(event.clone());
+            match event.0 {
+                Event::End(Tag::Image(_, _)) => break,
+                _ => caption.push(event)
+            }
+        }
+
+        let mut attribute = String::new();
+        loop {
+            let event = self.next_raw_event()?;
+            read_ahead.push(event.clone());
+            match event.0 {
+                Event::End(Tag::Paragraph) => break,
+                Event::Text(ref text) => attribute.push_str(text),
+                Event::SoftBreak => attribute.push(' '),
+                _ => {
+                    return None;
+                }
+            }
+        }
+        let style = FigureStyle::from_attribute(&attribute)?;
+        Some((url, title, caption, style))
+    }
+
+    fn render_figure(&mut self, url: Cow<'a, str>, title: Cow<'a, str>,
+                     caption: Vec<Event<'a>>, style: FigureStyle) -> Event<'a> {
+        let mut image = String::new();
+        let url = self.rewrite_link(url);
+        // the alt text is only the text of the caption, without its markup
+        // or math
+        let alt: Vec<Event<'a>> = caption.iter()
+            .filter(|e| matches!(e, Event::Text(_)))
+            .cloned()
+            .collect();
+        html::push_html(&mut image, vec![Event::Start(Tag::Image(url.clone(), title.clone()))]
+                        .into_iter()
+                        .chain(alt)
+                        .chain(vec![Event::End(Tag::Image(url, title))]));
+        let mut figcaption = String::new();
+        html::push_html(&mut figcaption, caption.into_iter());
+
+        let figure = match style {
+            FigureStyle::Margin if !self.as_footnotes => {
+                self.margin_notes += 1;
+                format!("<p>{}{}{}</span></p>\n", start_margin_note(self.margin_notes),
+                        image, figcaption)
+            },
+            _ => {
+                let class = match style {
+                    FigureStyle::Fullwidth => " class=\"fullwidth\"",
+                    _ => ""
+                };
+                let figcaption = if figcaption.is_empty() {
+                    figcaption
+                } else {
+                    format!("<figcaption>{}</figcaption>", figcaption)
+                };
+                format!("<figure{}>{}{}</figure>\n", class, image, figcaption)
+            }
+        };
+        Event::Html(Cow::from(figure))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::FigureStyle;
+
+    #[test]
+    fn can_read_figure_style() {
+        assert_eq!(FigureStyle::from_attribute(""), Some(FigureStyle::Normal));
+        assert_eq!(FigureStyle::from_attribute(" {.fullwidth}"), Some(FigureStyle::Fullwidth));
+        assert_eq!(FigureStyle::from_attribute("{.margin}"), Some(FigureStyle::Margin));
+        assert_eq!(FigureStyle::from_attribute("{a sidenote}"), None);
+    }
+}
@@ -0,0 +1,117 @@
+use pulldown_cmark::{Event, Tag, Parser, OPTION_ENABLE_FOOTNOTES};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use parser::SidenoteParser;
+use sidenote_error::SidenoteError;
+
+
+/// events together with their offsets in the markdown
+pub type RawEvents<'a> = Vec<(Event<'a>, usize, usize)>;
+
+pub type Footnotes<'a> = HashMap<String, RawEvents<'a>>;
+
+
+/// the markdown parser, with `[^1]` footnotes enabled
+pub fn markdown_parser(md: &str) -> Parser<'_> {
+    Parser::new_ext(md, OPTION_ENABLE_FOOTNOTES)
+}
+
+
+/// the events of every footnote definition in the markdown, by name,
+/// together with their offsets. The paragraph around a definition is
+/// dropped, as it is rendered inline as a sidenote.
+pub fn collect_footnotes(md: &str) -> Footnotes<'_> {
+    let mut footnotes = HashMap::new();
+    let mut definition: Option<(String, RawEvents)> = None;
+    let mut parser = markdown_parser(md);
+    loop {
+        let start = parser.get_offset();
+        let event = match parser.next() {
+            Some(e) => e,
+            None => break
+        };
+        let end = parser.get_offset();
+        match event {
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                definition = Some((name.to_string(), vec![]));
+            },
+            Event::End(Tag::FootnoteDefinition(_)) => {
+                if let Some((name, mut events)) = definition.take() {
+                    if let Some(&(Event::Start(Tag::Paragraph), _, _)) = events.first() {
+                        events.remove(0);
+                    }
+                    if let Some(&(Event::End(Tag::Paragraph), _, _)) = events.last() {
+                        events.pop();
+                    }
+                    footnotes.insert(name, events);
+                }
+            },
+            _ => {
+                if let Some((_, ref mut events)) = definition {
+                    events.push((event, start, end));
+                }
+            }
+        }
+    }
+    footnotes
+}
+
+
+/// Render markdown footnotes like sidenotes: a reference `[^1]` becomes a
+/// sidenote with the text of the definition `[^1]: ...`, which is left
+/// out where it is written.
+impl<'a> SidenoteParser<'a> {
+
+    pub fn parse_footnote_reference(&mut self, name: Cow<'a, str>) -> 
+        Result<Event<'a>, SidenoteError> {
+        let definition = match self.footnotes.get(name.as_ref()) {
+            Some(events) => events.clone(),
+            None => {
+                return Ok(Event::Text(Cow::from(format!("[^{}]", name))));
+            }
+        };
+        let (start, end) = self.span;
+        if self.in_sidenote_block {
+            return Err(SidenoteError::Nested(self.source_position(start)));
+        }
+        // the definition is read next, and closed by the end tag
+        self.lookahead.push((Event::End(Tag::FootnoteDefinition(name)), end, end));
+        self.lookahead.extend(definition.into_iter().rev());
+        Ok(self.open_sidenote(start))
+    }
+
+    pub fn skip_footnote_definition(&mut self) -> Event<'a> {
+        while let Some((event, _, _)) = self.next_raw_event() {
+            if let Event::End(Tag::FootnoteDefinition(_)) = event {
+                break;
+            }
+        }
+        Event::Text(Cow::from(""))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::{Event, Tag};
+    use std::borrow::Cow;
+    use super::collect_footnotes;
+
+    #[test]
+    fn can_collect_footnotes() {
+        let md = "Text[^1].\n\n[^1]: The *first* note.\n\n[^b]: Second.\n";
+        let footnotes = collect_footnotes(md);
+        assert_eq!(footnotes.len(), 2);
+        let first: Vec<Event> = footnotes["1"].iter().map(|e| e.0.clone()).collect();
+        assert_eq!(first, vec![
+            Event::Text(Cow::from("The ")),
+            Event::Start(Tag::Emphasis),
+            Event::Text(Cow::from("first")),
+            Event::End(Tag::Emphasis),
+            Event::Text(Cow::from(" note."))
+        ]);
+        let (_, start, end) = footnotes["b"][0];
+        assert_eq!(&md[start..end], "Second.");
+    }
+}
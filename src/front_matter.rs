@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use serde::{Deserialize, Deserializer};
+use serde::de;
+use serde_json::{self, Map, Value};
+use serde_yaml;
+use toml;
+
+use sidenote_error::SidenoteError;
+
+
+const YAML_DELIMITER: &str = "---";
+const TOML_DELIMITER: &str = "+++";
+
+
+/// Metadata declared in a block at the very top of a post's `index.md`,
+/// either as YAML between `---` lines or as TOML between `+++` lines.
+/// Any field not listed here ends up in `extra`, so that templates can
+/// use custom fields as `meta.<field>`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+    pub title: Option<String>,
+    #[serde(deserialize_with = "deserialize_date")]
+    pub date: Option<SystemTime>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub draft: bool,
+    #[serde(deserialize_with = "deserialize_date")]
+    pub publish_at: Option<SystemTime>,
+    pub slug: Option<String>,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>
+}
+
+
+/// parse a date, either as RFC 3339 or as a plain date or date and time,
+/// which are taken to be UTC.
+pub fn parse_date(s: &str) -> Option<SystemTime> {
+    if let Ok(d) = DateTime::parse_from_rfc3339(s) {
+        return Some(SystemTime::from(d));
+    }
+    for format in &["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(d) = NaiveDateTime::parse_from_str(s, format) {
+            return Some(SystemTime::from(DateTime::<Utc>::from_utc(d, Utc)));
+        }
+    }
+    match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        Ok(d) => Some(SystemTime::from(
+                DateTime::<Utc>::from_utc(d.and_hms(0, 0, 0), Utc))),
+        _ => None
+    }
+}
+
+
+fn deserialize_date<'de, D>(deserializer: D) -> Result<Option<SystemTime>, D::Error>
+    where D: Deserializer<'de> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) => match parse_date(&s) {
+            Some(t) => Ok(Some(t)),
+            None => Err(de::Error::custom(format!(
+                "can't understand the date '{}'. Use e.g. 2018-10-25 or 2018-10-25T18:30:00Z", s)))
+        },
+        None => Ok(None)
+    }
+}
+
+
+// TOML dates have their own type, which serde_json can't represent:
+// turn them into strings, like in YAML
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(a) => Value::Array(a.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(t) => Value::Object(t.into_iter()
+            .map(|(k, v)| (k, toml_to_json(v)))
+            .collect::<Map<String, Value>>())
+    }
+}
+
+
+fn parse_block(block: &str, delimiter: &str) -> Result<FrontMatter, SidenoteError> {
+    if block.trim().is_empty() {
+        return Ok(FrontMatter::default());
+    }
+    let value = if delimiter == YAML_DELIMITER {
+        match serde_yaml::from_str::<Value>(block) {
+            Ok(v) => v,
+            Err(e) => {
+                return Err(SidenoteError::FrontMatter(format!("invalid YAML: {}", e)));
+            }
+        }
+    } else {
+        match toml::from_str::<toml::Value>(block) {
+            Ok(v) => toml_to_json(v),
+            Err(e) => {
+                return Err(SidenoteError::FrontMatter(format!("invalid TOML: {}", e)));
+            }
+        }
+    };
+    match serde_json::from_value(value) {
+        Ok(f) => Ok(f),
+        Err(e) => Err(SidenoteError::FrontMatter(format!("{}", e)))
+    }
+}
+
+
+/// Split a markdown document into its front matter and the remaining
+/// markdown. Documents without front matter get the default, empty
+/// `FrontMatter` and are returned unchanged.
+pub fn split_front_matter(md: &str) -> Result<(FrontMatter, &str), SidenoteError> {
+    let first_line = md.lines().next().unwrap_or("");
+    let delimiter = match first_line.trim_end() {
+        YAML_DELIMITER => YAML_DELIMITER,
+        TOML_DELIMITER => TOML_DELIMITER,
+        _ => {
+            return Ok((FrontMatter::default(), md));
+        }
+    };
+
+    let block_start = md.find('\n').map(|i| i + 1).unwrap_or_else(|| md.len());
+    let mut line_start = block_start;
+    for line in md[block_start..].split('\n') {
+        let line_end = line_start + line.len();
+        if line.trim_end() == delimiter {
+            let front_matter = parse_block(&md[block_start..line_start], delimiter)?;
+            let body_start = if line_end < md.len() { line_end + 1 } else { line_end };
+            return Ok((front_matter, &md[body_start..]));
+        }
+        line_start = line_end + 1;
+    }
+    Err(SidenoteError::FrontMatter(format!(
+        "the front matter block is not closed. Please end it with a line containing only '{}'",
+        delimiter)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use serde_json::Value;
+    use super::{split_front_matter, parse_date, FrontMatter};
+
+    #[test]
+    fn no_front_matter() {
+        let md = "hello\n=====\n\nsome text\n";
+        let (front_matter, body) = split_front_matter(md).unwrap();
+        assert_eq!(front_matter, FrontMatter::default());
+        assert_eq!(body, md);
+    }
+
+    #[test]
+    fn can_parse_yaml() {
+        let md = r#"---
+title: A title
+date: 2018-10-25
+publish_at: 2018-10-25T17:30:00Z
+tags: [rust, blogging]
+draft: true
+mood: cheerful
+---
+hello
+=====
+"#;
+        let (front_matter, body) = split_front_matter(md).unwrap();
+        assert_eq!(body, "hello\n=====\n");
+        assert_eq!(front_matter.title, Some("A title".to_string()));
+        assert_eq!(front_matter.date, Some(UNIX_EPOCH + Duration::from_secs(1540425600)));
+        assert_eq!(front_matter.publish_at, Some(UNIX_EPOCH + Duration::from_secs(1540488600)));
+        assert_eq!(front_matter.tags, vec!["rust", "blogging"]);
+        assert!(front_matter.draft);
+        assert_eq!(front_matter.slug, None);
+        assert_eq!(front_matter.extra["mood"], Value::from("cheerful"));
+    }
+
+    #[test]
+    fn can_parse_toml() {
+        let md = "+++\r\ntitle = \"A title\"\r\ndate = 2018-10-25T00:00:00Z\r\nslug = \"a-title\"\r\n+++\r\nhello";
+        let (front_matter, body) = split_front_matter(md).unwrap();
+        assert_eq!(body, "hello");
+        assert_eq!(front_matter.title, Some("A title".to_string()));
+        assert_eq!(front_matter.date, Some(UNIX_EPOCH + Duration::from_secs(1540425600)));
+        assert_eq!(front_matter.slug, Some("a-title".to_string()));
+    }
+
+    #[test]
+    fn empty_front_matter() {
+        let (front_matter, body) = split_front_matter("---\n---\n").unwrap();
+        assert_eq!(front_matter, FrontMatter::default());
+        assert_eq!(body, "");
+    }
+
+    #[test]
+    fn catch_front_matter_errors() {
+        assert!(split_front_matter("---\ntitle: A title\n\nhello").is_err());
+        assert!(split_front_matter("---\ndate: yesterday\n---\nhello").is_err());
+        assert!(split_front_matter("+++\ntitle = \n+++\nhello").is_err());
+    }
+
+    #[test]
+    fn can_parse_dates() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(1540488600));
+        assert_eq!(parse_date("2018-10-25T17:30:00Z"), expected);
+        assert_eq!(parse_date("2018-10-25T18:30:00+01:00"), expected);
+        assert_eq!(parse_date("2018-10-25 17:30:00"), expected);
+        assert_eq!(parse_date("2018-10-25 17:30"), expected);
+        assert_eq!(parse_date("25/10/2018"), None);
+    }
+}
@@ -0,0 +1,96 @@
+use pulldown_cmark::{Event, Parser, Tag};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+
+/// 64-bit FNV-1a, which unlike the hasher of the standard library stays
+/// the same across versions of Rust, so the hashes can be persisted
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+
+/// hash a single text, like a template
+pub fn text_hash(text: &str) -> String {
+    format!("{:016x}", fnv1a(FNV_OFFSET, text.as_bytes()))
+}
+
+
+/// the files in the post directory that `markdown` links to or shows as
+/// images, like `images/a.png`
+fn local_assets(post_dir: &Path, markdown: &str) -> Vec<PathBuf> {
+    let mut assets = vec![];
+    for event in Parser::new(markdown) {
+        let url = match event {
+            Event::Start(Tag::Image(url, _)) | Event::Start(Tag::Link(url, _)) => url,
+            _ => continue
+        };
+        let url = url.split(['?', '#']).next().unwrap_or("");
+        if url.is_empty() || url.starts_with('/') || url.contains("://")
+            || url.starts_with("mailto:") || url.contains("{{") {
+            continue;
+        }
+        let asset = post_dir.join(url);
+        if asset.is_file() && !assets.contains(&asset) {
+            assets.push(asset);
+        }
+    }
+    assets.sort();
+    assets
+}
+
+
+/// Hash the markdown of a post together with the local files it refers
+/// to, to tell whether the post changed since it was last synced.
+pub fn content_hash(post_dir: &Path, markdown: &str) -> String {
+    let mut hash = fnv1a(FNV_OFFSET, markdown.as_bytes());
+    for asset in local_assets(post_dir, markdown) {
+        // an asset that can't be read counts as empty
+        let content = fs::read(&asset).unwrap_or_default();
+        hash = fnv1a(hash, asset.strip_prefix(post_dir).unwrap_or(&asset)
+                     .to_string_lossy().as_bytes());
+        hash = fnv1a(hash, &content);
+    }
+    format!("{:016x}", hash)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use super::{fnv1a, local_assets, content_hash, text_hash, FNV_OFFSET};
+
+    #[test]
+    fn can_hash_content() {
+        assert_eq!(fnv1a(FNV_OFFSET, b""), FNV_OFFSET);
+        assert_eq!(fnv1a(FNV_OFFSET, b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(text_hash("a"), "af63dc4c8601ec8c");
+
+        let mut post = env::temp_dir();
+        post.push("wellington-hash");
+        let _ = fs::remove_dir_all(&post);
+        fs::create_dir_all(post.join("images")).unwrap();
+        fs::write(post.join("images/a.png"), "a").unwrap();
+        fs::write(post.join("notes.txt"), "notes").unwrap();
+        let markdown = concat!("![a](images/a.png) [notes](notes.txt?v=1) ",
+                               "[gone](missing.png) [elsewhere](https://example.com/a.png)");
+        assert_eq!(local_assets(&post, markdown),
+                   vec![post.join("images/a.png"), post.join("notes.txt")]);
+
+        let before = content_hash(&post, markdown);
+        assert_eq!(content_hash(&post, markdown), before);
+        fs::write(post.join("images/a.png"), "b").unwrap();
+        assert!(content_hash(&post, markdown) != before);
+        assert!(content_hash(&post, "") != content_hash(&post, "different"));
+        fs::remove_dir_all(&post).unwrap();
+    }
+}
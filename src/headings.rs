@@ -0,0 +1,255 @@
+use pulldown_cmark::{Event, Tag};
+use std::borrow::Cow;
+use handlebars::html_escape;
+
+use parser::SidenoteParser;
+use variables::Variables;
+
+
+/// a heading of level 2 to 6, as listed in the table of contents of a
+/// post. Headings of a deeper level than the previous one are its
+/// `children`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub id: String,
+    pub title: String,
+    pub level: i32,
+    pub children: Vec<Heading>
+}
+
+
+impl Heading {
+    pub fn new(id: &str, title: &str, level: i32) -> Heading {
+        Heading{id: id.to_string(), title: title.to_string(), level, children: vec![]}
+    }
+}
+
+
+/// turn a heading into an id for the URL: lowercase letters and numbers,
+/// with dashes between words
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.is_empty()
+            && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "section".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+
+// the position of the `$` or `$$` closing math opened just before `start`,
+// following the rules of `find_closing_math` for sidenotes
+fn closing_math(chars: &[(char, bool)], start: usize, display: bool) -> Option<usize> {
+    let opens = match chars.get(start) {
+        Some(&(c, _)) => display || !c.is_whitespace(),
+        None => false
+    };
+    if !opens {
+        return None;
+    }
+    (start..chars.len()).find(|&j| {
+        chars[j] == ('$', false) && if display {
+            chars.get(j + 1) == Some(&('$', false))
+        } else {
+            j > start && !chars[j - 1].0.is_whitespace()
+                && !chars.get(j + 1).is_some_and(|c| c.0.is_ascii_digit())
+        }
+    })
+}
+
+
+/// the text of a heading for its id and the table of contents, from its
+/// characters, each marked if it is literal: escaped or in code. Sidenotes
+/// and math are left out, and variables substituted.
+fn heading_text(chars: &[(char, bool)], variables: &Variables) -> String {
+    let mut text = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (c, literal) = chars[i];
+        i += 1;
+        if literal {
+            text.push(c);
+            // `\{{name}}` is a literal variable
+            if c == '{' && chars.get(i) == Some(&('{', false)) {
+                while i < chars.len() && !text.ends_with("}}") {
+                    text.push(chars[i].0);
+                    i += 1;
+                }
+            }
+            continue;
+        }
+        match c {
+            '{' if chars.get(i) == Some(&('{', false)) => {
+                let mut name = String::new();
+                i += 1;
+                while i < chars.len() && !(chars[i] == ('}', false)
+                                           && chars.get(i + 1) == Some(&('}', false))) {
+                    name.push(chars[i].0);
+                    i += 1;
+                }
+                i += 2;
+                if let Some(value) = variables.get(name.trim()) {
+                    text.push_str(value);
+                }
+            },
+            '{' => {
+                while i < chars.len() && chars[i] != ('}', false) {
+                    i += 1;
+                }
+                i += 1;
+            },
+            '$' => {
+                let display = chars.get(i) == Some(&('$', false));
+                let start = if display { i + 1 } else { i };
+                match closing_math(chars, start, display) {
+                    Some(end) => {
+                        i = if display { end + 2 } else { end + 1 };
+                    },
+                    None => text.push('$')
+                }
+            },
+            _ => text.push(c)
+        }
+    }
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+
+fn insert_heading(toc: &mut Vec<Heading>, heading: Heading) {
+    match toc.last_mut() {
+        Some(ref mut last) if last.level < heading.level => {
+            insert_heading(&mut last.children, heading);
+        },
+        _ => toc.push(heading)
+    }
+}
+
+
+/// nest the headings of a post in the order they appear, for the post
+/// template's `toc`
+pub fn nest_headings(headings: Vec<Heading>) -> Vec<Heading> {
+    let mut toc = vec![];
+    for heading in headings {
+        insert_heading(&mut toc, heading);
+    }
+    toc
+}
+
+
+/// Give headings an id to link to, and a `#` link to themselves, which
+/// the CSS can show on hover. The headings are listed for the table of
+/// contents.
+impl<'a> SidenoteParser<'a> {
+
+    // the id isn't unique if the same heading appears twice
+    fn unique_heading_id(&self, text: &str) -> String {
+        let slug = slugify(text);
+        let mut id = slug.clone();
+        let mut n = 0;
+        while self.headings.iter().any(|h| h.id == id) {
+            n += 1;
+            id = format!("{}-{}", slug, n);
+        }
+        id
+    }
+
+    /// read ahead to the end of the heading for its text
+    pub fn start_heading(&mut self, level: i32) -> Event<'a> {
+        let mut read_ahead = vec![];
+        let mut chars = vec![];
+        let mut in_code = false;
+        while let Some((event, start, end)) = self.next_raw_event() {
+            let done = match event {
+                Event::End(Tag::Header(_)) => true,
+                Event::Start(Tag::Code) | Event::End(Tag::Code) => {
+                    in_code = !in_code;
+                    false
+                },
+                Event::Text(ref t) => {
+                    // escaped characters differ from their source
+                    let literal = in_code || (!self.raw(start, end).is_empty()
+                                              && self.raw(start, end) != t);
+                    chars.extend(t.chars().map(|c| (c, literal)));
+                    false
+                },
+                _ => false
+            };
+            read_ahead.push((event, start, end));
+            if done {
+                break;
+            }
+        }
+        read_ahead.reverse();
+        self.lookahead.extend(read_ahead);
+
+        let text = heading_text(&chars, &self.variables);
+        let id = self.unique_heading_id(&text);
+        let start = format!("<h{} id=\"{}\">", level, id);
+        self.headings.push(Heading::new(&id, &html_escape(&text), level));
+        Event::InlineHtml(Cow::from(start))
+    }
+
+    pub fn end_heading(&self, level: i32) -> Event<'a> {
+        let id = match self.headings.last() {
+            Some(h) => h.id.clone(),
+            None => "".to_string()
+        };
+        Event::InlineHtml(Cow::from(format!(
+            "<a class=\"anchor\" href=\"#{}\">#</a></h{}>\n", id, level)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{slugify, nest_headings, heading_text, Heading};
+    use variables::Variables;
+
+    #[test]
+    fn can_slugify() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  Ünïcode -- and_more 2 "), "ünïcode-and-more-2");
+        assert_eq!(slugify("?!"), "section");
+    }
+
+    #[test]
+    fn can_leave_notes_out_of_headings() {
+        let chars = |text: &str| text.chars().map(|c| (c, c == '#')).collect::<Vec<(char, bool)>>();
+        let mut variables = Variables::new();
+        variables.insert("name".to_string(), "Ada".to_string());
+        assert_eq!(heading_text(&chars("Results{see appendix}"), &variables), "Results");
+        assert_eq!(heading_text(&chars("$x^2$ math and $$y$$ more"), &variables),
+                   "math and more");
+        assert_eq!(heading_text(&chars("From $5 to $10"), &variables), "From $5 to $10");
+        assert_eq!(heading_text(&chars("Hi {{ name }}{-a note}!"), &variables), "Hi Ada!");
+        assert_eq!(heading_text(&[('{', true), ('a', false), ('}', true)], &variables), "{a}");
+        let mut escaped = vec![('{', true)];
+        escaped.extend("{name}} x".chars().map(|c| (c, false)));
+        assert_eq!(heading_text(&escaped, &variables), "{{name}} x");
+    }
+
+    #[test]
+    fn can_nest_headings() {
+        let toc = nest_headings(vec![
+            Heading::new("a", "A", 2),
+            Heading::new("b", "B", 3),
+            Heading::new("c", "C", 4),
+            Heading::new("d", "D", 3),
+            Heading::new("e", "E", 2),
+        ]);
+        assert_eq!(toc.len(), 2);
+        assert_eq!(toc[0].children.len(), 2);
+        assert_eq!(toc[0].children[0].children, vec![Heading::new("c", "C", 4)]);
+        assert_eq!(toc[0].children[1].id, "d");
+        assert!(toc[1].children.is_empty());
+    }
+}
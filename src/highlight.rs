@@ -0,0 +1,87 @@
+use std::sync::OnceLock;
+use syntect::highlighting::ThemeSet;
+use syntect::html::{ClassedHTMLGenerator, ClassStyle, css_for_theme_with_class_style};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+
+/// the theme used for `highlight.css` unless the blog configures another one
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+
+// prefix the classes, so that they don't clash with tufte-css
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed{prefix: "hl-"};
+
+
+// loading the bundled grammars is slow, so only do it once
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+
+/// Highlight `code` written in `lang`, the info string of a fenced code
+/// block such as "rust" or "py". Returns `None` for unknown languages,
+/// which are then rendered as plain code blocks. The spans carry classes
+/// only: the colours come from `highlight.css`, see `css_for_theme`.
+pub fn highlight(code: &str, lang: &str) -> Option<String> {
+    let token = lang.split(|c: char| c == ',' || c.is_whitespace()).next()?;
+    if token.is_empty() {
+        return None;
+    }
+    let syntax_set = syntax_set();
+    let syntax = syntax_set.find_syntax_by_token(token)?;
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(
+        syntax, syntax_set, CLASS_STYLE);
+    for line in LinesWithEndings::from(code) {
+        if generator.parse_html_for_line_which_includes_newline(line).is_err() {
+            return None;
+        }
+    }
+    Some(generator.finalize())
+}
+
+
+/// the names of the bundled themes
+pub fn theme_names() -> Vec<String> {
+    ThemeSet::load_defaults().themes.keys().cloned().collect()
+}
+
+
+/// the stylesheet colouring highlighted code with one of the bundled themes
+pub fn css_for_theme(name: &str) -> Result<String, String> {
+    let themes = ThemeSet::load_defaults();
+    let theme = match themes.themes.get(name) {
+        Some(t) => t,
+        None => {
+            return Err(format!("Unknown theme {}. Choose one of: {}",
+                               name, theme_names().join(", ")));
+        }
+    };
+    match css_for_theme_with_class_style(theme, CLASS_STYLE) {
+        Ok(css) => Ok(css),
+        Err(e) => Err(format!("Couldn't create stylesheet: {}", e))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, css_for_theme, theme_names, DEFAULT_THEME};
+
+    #[test]
+    fn can_highlight() {
+        let html = highlight("fn main() {}\n", "rust").expect("Can't highlight rust");
+        assert!(html.contains("<span class=\"hl-source hl-rust\">"));
+        assert!(html.contains("<span class=\"hl-storage hl-type hl-function hl-rust\">fn</span>"));
+        assert!(highlight("fn main() {}\n", "rust,ignore").is_some());
+        assert_eq!(highlight("bla", "not-a-language"), None);
+        assert_eq!(highlight("bla", ""), None);
+    }
+
+    #[test]
+    fn can_make_css() {
+        assert!(theme_names().contains(&DEFAULT_THEME.to_string()));
+        assert!(css_for_theme(DEFAULT_THEME).unwrap().contains(".hl-"));
+        assert!(css_for_theme("not-a-theme").is_err());
+    }
+}
@@ -10,14 +10,33 @@ extern crate handlebars;
 extern crate chrono;
 extern crate url;
 extern crate url_serde;
+extern crate serde_yaml;
+extern crate toml;
+extern crate syntect;
 
 mod sidenote_error;
+mod front_matter;
 mod parser;
 mod sidenotes;
+mod footnotes;
+mod headings;
+mod figures;
+mod variables;
 mod toc;
+mod tags;
+mod sitemap;
+mod highlight;
+mod hash;
+mod math;
 pub mod rss;
 pub mod templates;
+pub mod serve;
+pub mod watch;
 
-pub use parser::{html_from_markdown, ParsedMarkdown, PostData};
+pub use parser::{html_from_markdown, feed_html_from_markdown, html_from_markdown_with_variables,
+                 feed_html_from_markdown_with_variables, ParsedMarkdown, PostData};
+pub use variables::Variables;
 pub use toc::{Blog, IndexedBlogPost};
+pub use front_matter::FrontMatter;
+pub use headings::Heading;
 
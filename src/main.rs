@@ -5,12 +5,14 @@ extern crate handlebars;
 use std::env;
 use std::fs;
 use std::path::PathBuf;
-use getopts::Options;
+use getopts::{Matches, Options};
 use handlebars::no_escape;
 
-use wellington::{html_from_markdown, Blog, PostData, IndexedBlogPost};
+use wellington::{html_from_markdown_with_variables, Blog, PostData, IndexedBlogPost, Variables};
 use wellington::templates::{AllTemplates, POST_TEMPLATE};
 use wellington::rss::CoreData;
+use wellington::serve;
+use wellington::watch;
 
 
 fn usage(program: &str, init_opts: &str) -> String {
@@ -24,7 +26,20 @@ Where command is one of:
                                 
                                 If no posts were updated, the index and posts 
                                 won't be re-rendered, unless you use the -f flag. 
-                                Use this flag when changing templates, for example.
+                                Changed templates are noticed without it: what
+                                they render is written again.
+
+    watch                       Keep syncing the blog while you write: whenever a
+                                post changes, it is rendered again, along with the
+                                feeds and the sitemap. The table of contents is
+                                only rewritten when a post's title or date changes,
+                                or when posts are added or removed.
+
+    serve [-p PORT]             Serve the blog on http://127.0.0.1:PORT (4000 by
+                                default) for previewing. The server is rooted one
+                                level above the blog directory. Whenever a post or
+                                template changes, the blog is synced and the open
+                                pages reload.
 
     init <options>              Initialise the current directory as a blog. You must 
                                 provide the following options:{}
@@ -53,7 +68,14 @@ fn convert(input_filename: &str, output_filename: &str) {
             std::process::exit(1);
         }
     };
-    let output = match html_from_markdown(&input, "".to_string()) {
+    // the post and index URLs are empty, as for the template below
+    let mut variables = Variables::new();
+    variables.insert("post_url".to_string(), "".to_string());
+    variables.insert("index_url".to_string(), "".to_string());
+    if let Ok(core_data) = CoreData::load() {
+        variables.insert("blog_title".to_string(), core_data.title().to_string());
+    }
+    let output = match html_from_markdown_with_variables(&input, "".to_string(), &variables) {
         Ok(ht) => ht,
         Err(err) => {
             println!("{}", err);
@@ -64,7 +86,7 @@ fn convert(input_filename: &str, output_filename: &str) {
     bp.set_title(&output.title);
 
     let data = PostData::from((output.html.as_str(), &mut bp, "", "".to_string(),
-        output.sidenotes));
+        output.sidenotes, output.headings));
     let rendered = match data.render(&template) {
         Ok(ht) => ht,
         Err(err) => {
@@ -87,12 +109,17 @@ fn current_dir() -> PathBuf {
 }
 
 
-fn init(title: &str,
-        home_url: &str,
-        description: &str,
-        author: &str,
-        post: Option<String>, 
-        index: Option<String>) {
+fn init(matches: &Matches) {
+    let posts_per_page = match matches.opt_str("per_page") {
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) if n > 0 => Some(n),
+            _ => {
+                eprintln!("Error: --per_page must be a positive number");
+                std::process::exit(1);
+            }
+        },
+        None => None
+    };
     let mut blog = match Blog::new(current_dir()) {
         Ok(b) => b,
         Err(e) => {
@@ -100,8 +127,11 @@ fn init(title: &str,
             std::process::exit(1);
         }
     };
-    let core_data = match CoreData::new(
-        title, home_url, description, author,
+    let mut core_data = match CoreData::new(
+        &matches.opt_str("title").unwrap(),
+        &matches.opt_str("home_url").unwrap(),
+        &matches.opt_str("desc").unwrap(),
+        &matches.opt_str("author").unwrap(),
         &blog.index_url) {
         Ok(d) => d,
         Err(err) => {
@@ -109,7 +139,13 @@ fn init(title: &str,
             std::process::exit(1);
         }
     };
-    match blog.init(core_data, post, index) {
+    core_data.set_posts_per_page(posts_per_page);
+    if let Err(err) = core_data.set_highlight_theme(matches.opt_str("highlight_theme")) {
+        println!("{}", err);
+        std::process::exit(1);
+    }
+    match blog.init(core_data, matches.opt_str("post"), matches.opt_str("index"),
+                    matches.opt_str("tags"), matches.opt_str("rss")) {
         Ok(_) => println!("Initialised new empty blog"),
         Err(e)  => println!("{}", e)
     }
@@ -134,6 +170,28 @@ fn sync(force: bool) {
 }
 
 
+fn serve(matches: &Matches) {
+    if !matches.free.is_empty() {
+        eprintln!("Error: unexpected argument {}", matches.free[0]);
+        std::process::exit(1);
+    }
+    let port = match matches.opt_str("port") {
+        Some(p) => match p.parse::<u16>() {
+            Ok(p) => p,
+            _ => {
+                eprintln!("Error: the port must be a number");
+                std::process::exit(1);
+            }
+        },
+        None => 4000
+    };
+    if let Err(e) = serve::serve(current_dir(), port) {
+        println!("Couldn't serve: {}", e);
+        std::process::exit(1);
+    }
+}
+
+
 fn main() {
     let args :Vec<String> = env::args().collect();
     let mut init_opts = Options::new();
@@ -142,10 +200,22 @@ fn main() {
     for example https://myblog.com", "HOME_URL");
     init_opts.reqopt("d", "desc", "Describe your blog", "BLOG_DESCRIPTION");
     init_opts.reqopt("a", "author", "Who are you? Please give your name. This will be make public in the RSS feed", "BLOG_AUTHOR");
+    init_opts.optopt("n", "per_page", "(Optional) Number of posts on each page of the table of contents",
+                     "POSTS_PER_PAGE");
+    init_opts.optopt("s", "highlight_theme", "(Optional) Theme for highlighting code, e.g. 'Solarized (dark)'",
+                     "HIGHLIGHT_THEME");
     init_opts.optopt("p", "post", "(Optional) Template for rendering individual posts", 
                      "POST_TEMPLATE");
     init_opts.optopt("i", "index", "(Optional) Template for rendering the table of contents", 
                      "INDEX_TEMPLATE");
+    init_opts.optopt("g", "tags", "(Optional) Template for rendering the tag pages", 
+                     "TAGS_TEMPLATE");
+    init_opts.optopt("r", "rss", "(Optional) Template for rendering the RSS feed", 
+                     "RSS_TEMPLATE");
+
+    let mut serve_opts = Options::new();
+    serve_opts.optopt("p", "port", "(Optional) Port to serve the blog on, 4000 by default",
+                      "PORT");
 
     if args.len() == 1 {
         eprintln!("{}", usage(&args[0], &init_opts.usage("")));
@@ -165,6 +235,17 @@ fn main() {
         } else {
             sync(false);
         }
+    } else if command == "watch" {
+        watch::watch(current_dir());
+    } else if command == "serve" {
+        let matches = match serve_opts.parse(&args[2..]) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("Error: {}", e.to_string());
+                std::process::exit(1);
+            }
+        };
+        serve(&matches);
     } else if command == "init" {
         let matches = match init_opts.parse(&args[1..]) {
             Ok(m) => m,
@@ -173,12 +254,7 @@ fn main() {
                 std::process::exit(1);
             }
         };
-    init(&matches.opt_str("title").unwrap(),
-         &matches.opt_str("home_url").unwrap(),
-         &matches.opt_str("desc").unwrap(),
-         &matches.opt_str("author").unwrap(),
-         matches.opt_str("post"), 
-         matches.opt_str("index"));
+        init(&matches);
     } else {
         eprintln!("I don't recognise this command :(");
     }
@@ -0,0 +1,664 @@
+use sidenote_error::SidenoteError;
+
+
+/// How an item takes sub- and superscripts: big operators like `\sum`
+/// and functions like `\lim` take them below and above in display math.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Kind {
+    Ordinary,
+    Limits
+}
+
+
+struct Item {
+    html: String,
+    kind: Kind
+}
+
+
+impl Item {
+    fn new(html: String) -> Self {
+        Item{html, kind: Kind::Ordinary}
+    }
+}
+
+
+/// Why `parse_row` stopped reading
+#[derive(Debug, PartialEq)]
+enum Stop {
+    Eof,
+    Close,
+    Right,
+    Align,
+    NewLine,
+    End(String)
+}
+
+
+const GREEK: &[(&str, &str)] = &[
+    ("alpha", "α"), ("beta", "β"), ("gamma", "γ"), ("delta", "δ"),
+    ("epsilon", "ϵ"), ("varepsilon", "ε"), ("zeta", "ζ"), ("eta", "η"),
+    ("theta", "θ"), ("vartheta", "ϑ"), ("iota", "ι"), ("kappa", "κ"),
+    ("lambda", "λ"), ("mu", "μ"), ("nu", "ν"), ("xi", "ξ"), ("pi", "π"),
+    ("varpi", "ϖ"), ("rho", "ρ"), ("varrho", "ϱ"), ("sigma", "σ"),
+    ("varsigma", "ς"), ("tau", "τ"), ("upsilon", "υ"), ("phi", "ϕ"),
+    ("varphi", "φ"), ("chi", "χ"), ("psi", "ψ"), ("omega", "ω")
+];
+
+// upright, unlike the lowercase letters
+const UPPER_GREEK: &[(&str, &str)] = &[
+    ("Gamma", "Γ"), ("Delta", "Δ"), ("Theta", "Θ"), ("Lambda", "Λ"),
+    ("Xi", "Ξ"), ("Pi", "Π"), ("Sigma", "Σ"), ("Upsilon", "Υ"),
+    ("Phi", "Φ"), ("Psi", "Ψ"), ("Omega", "Ω")
+];
+
+const IDENTIFIERS: &[(&str, &str)] = &[
+    ("infty", "∞"), ("partial", "∂"), ("nabla", "∇"), ("emptyset", "∅"),
+    ("varnothing", "∅"), ("ell", "ℓ"), ("hbar", "ℏ"), ("aleph", "ℵ"),
+    ("Re", "ℜ"), ("Im", "ℑ"), ("wp", "℘"), ("top", "⊤"), ("bot", "⊥")
+];
+
+const OPERATORS: &[(&str, &str)] = &[
+    ("cdot", "⋅"), ("times", "×"), ("div", "÷"), ("pm", "±"), ("mp", "∓"),
+    ("ast", "∗"), ("star", "⋆"), ("circ", "∘"), ("bullet", "∙"),
+    ("oplus", "⊕"), ("ominus", "⊖"), ("otimes", "⊗"), ("odot", "⊙"),
+    ("leq", "≤"), ("le", "≤"), ("geq", "≥"), ("ge", "≥"), ("neq", "≠"),
+    ("ne", "≠"), ("ll", "≪"), ("gg", "≫"), ("approx", "≈"), ("equiv", "≡"),
+    ("sim", "∼"), ("simeq", "≃"), ("cong", "≅"), ("propto", "∝"),
+    ("prec", "≺"), ("succ", "≻"), ("preceq", "⪯"), ("succeq", "⪰"),
+    ("in", "∈"), ("notin", "∉"), ("ni", "∋"), ("subset", "⊂"),
+    ("supset", "⊃"), ("subseteq", "⊆"), ("supseteq", "⊇"), ("cup", "∪"),
+    ("cap", "∩"), ("setminus", "∖"), ("wedge", "∧"), ("land", "∧"),
+    ("vee", "∨"), ("lor", "∨"), ("neg", "¬"), ("lnot", "¬"),
+    ("forall", "∀"), ("exists", "∃"), ("nexists", "∄"),
+    ("to", "→"), ("rightarrow", "→"), ("leftarrow", "←"), ("gets", "←"),
+    ("leftrightarrow", "↔"), ("Rightarrow", "⇒"), ("Leftarrow", "⇐"),
+    ("Leftrightarrow", "⇔"), ("implies", "⟹"), ("impliedby", "⟸"),
+    ("iff", "⟺"), ("mapsto", "↦"), ("uparrow", "↑"), ("downarrow", "↓"),
+    ("longrightarrow", "⟶"), ("longleftarrow", "⟵"),
+    ("mid", "∣"), ("parallel", "∥"), ("perp", "⊥"), ("angle", "∠"),
+    ("vdash", "⊢"), ("models", "⊨"), ("colon", ":"),
+    ("ldots", "…"), ("dots", "…"), ("cdots", "⋯"), ("vdots", "⋮"),
+    ("ddots", "⋱"), ("prime", "′"),
+    ("langle", "⟨"), ("rangle", "⟩"), ("lbrace", "{"), ("rbrace", "}"),
+    ("lvert", "|"), ("rvert", "|"), ("vert", "|"), ("lVert", "‖"),
+    ("rVert", "‖"), ("Vert", "‖"), ("lfloor", "⌊"), ("rfloor", "⌋"),
+    ("lceil", "⌈"), ("rceil", "⌉"), ("backslash", "∖")
+];
+
+// take their limits below and above in display math
+const BIG_OPERATORS: &[(&str, &str)] = &[
+    ("sum", "∑"), ("prod", "∏"), ("coprod", "∐"), ("bigcup", "⋃"),
+    ("bigcap", "⋂"), ("bigoplus", "⨁"), ("bigotimes", "⨂"),
+    ("bigvee", "⋁"), ("bigwedge", "⋀")
+];
+
+const INTEGRALS: &[(&str, &str)] = &[
+    ("int", "∫"), ("iint", "∬"), ("iiint", "∭"), ("oint", "∮")
+];
+
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan",
+    "sinh", "cosh", "tanh", "coth", "log", "ln", "lg", "exp", "arg", "deg",
+    "dim", "hom", "ker"
+];
+
+const LIMIT_FUNCTIONS: &[&str] = &[
+    "lim", "liminf", "limsup", "max", "min", "sup", "inf", "det", "gcd", "Pr"
+];
+
+const ACCENTS: &[(&str, &str)] = &[
+    ("hat", "^"), ("widehat", "^"), ("bar", "¯"), ("overline", "¯"),
+    ("vec", "→"), ("overrightarrow", "→"), ("tilde", "~"),
+    ("widetilde", "~"), ("dot", "˙"), ("ddot", "¨"), ("check", "ˇ"),
+    ("breve", "˘"), ("acute", "´"), ("grave", "`"), ("overbrace", "⏞")
+];
+
+const UNDER_ACCENTS: &[(&str, &str)] = &[
+    ("underline", "_"), ("underbrace", "⏟")
+];
+
+const VARIANTS: &[(&str, &str)] = &[
+    ("mathrm", "normal"), ("mathbf", "bold"), ("boldsymbol", "bold-italic"),
+    ("mathit", "italic"), ("mathbb", "double-struck"), ("mathcal", "script"),
+    ("mathscr", "script"), ("mathfrak", "fraktur"), ("mathsf", "sans-serif"),
+    ("mathtt", "monospace")
+];
+
+const SPACES: &[(&str, &str)] = &[
+    (",", "0.167em"), (":", "0.222em"), (">", "0.222em"), (";", "0.278em"),
+    ("!", "-0.167em"), (" ", "0.333em"), ("quad", "1em"), ("qquad", "2em")
+];
+
+// commands that only change the size of what follows
+const IGNORED: &[&str] = &[
+    "displaystyle", "textstyle", "scriptstyle", "limits", "nolimits",
+    "big", "Big", "bigg", "Bigg", "bigl", "bigr", "Bigl", "Bigr",
+    "biggl", "biggr", "Biggl", "Biggr"
+];
+
+
+fn lookup<'b>(table: &[(&str, &'b str)], name: &str) -> Option<&'b str> {
+    table.iter().find(|entry| entry.0 == name).map(|entry| entry.1)
+}
+
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+
+fn mrow(items: Vec<Item>) -> String {
+    if items.len() == 1 {
+        items.into_iter().next().unwrap().html
+    } else {
+        let mut html = "<mrow>".to_string();
+        for item in items {
+            html.push_str(&item.html);
+        }
+        html.push_str("</mrow>");
+        html
+    }
+}
+
+
+fn operator(op: &str) -> Item {
+    Item::new(format!("<mo>{}</mo>", escape(op)))
+}
+
+
+fn fence(delimiter: &str) -> String {
+    if delimiter.is_empty() {
+        String::new()
+    } else {
+        format!("<mo fence=\"true\" stretchy=\"true\">{}</mo>", escape(delimiter))
+    }
+}
+
+
+struct MathParser {
+    chars: Vec<char>,
+    pos: usize,
+    display: bool,
+    variant: Option<&'static str>
+}
+
+
+impl MathParser {
+
+    fn new(tex: &str, display: bool) -> Self {
+        MathParser{chars: tex.chars().collect(), pos: 0, display, variant: None}
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    // the name of a command, just after its backslash: either a run of
+    // letters or a single other character, as in `\{` or `\,`
+    fn read_command(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while self.pos < self.chars.len() && self.chars[self.pos].is_ascii_alphabetic() {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            match self.chars.get(self.pos) {
+                Some(c) => {
+                    self.pos += 1;
+                    Ok(c.to_string())
+                },
+                None => Err("a backslash at the end".to_string())
+            }
+        } else {
+            Ok(self.chars[start..self.pos].iter().collect())
+        }
+    }
+
+    // the raw text of a `{...}` group, for `\text` and `\begin`
+    fn read_raw_group(&mut self) -> Result<String, String> {
+        if self.next_char() != Some('{') {
+            return Err("expected a {".to_string());
+        }
+        let mut depth = 0;
+        let mut text = String::new();
+        while let Some(&c) = self.chars.get(self.pos) {
+            self.pos += 1;
+            match c {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Ok(text),
+                '}' => depth -= 1,
+                _ => ()
+            }
+            text.push(c);
+        }
+        Err("a { is never closed".to_string())
+    }
+
+    fn identifier(&self, name: &str) -> Item {
+        match self.variant {
+            Some(v) => Item::new(format!("<mi mathvariant=\"{}\">{}</mi>", v, escape(name))),
+            None => Item::new(format!("<mi>{}</mi>", escape(name)))
+        }
+    }
+
+    fn number(&mut self) -> Item {
+        let start = self.pos;
+        while self.pos < self.chars.len() && (self.chars[self.pos].is_ascii_digit()
+            || (self.chars[self.pos] == '.' && self.pos + 1 < self.chars.len()
+                && self.chars[self.pos + 1].is_ascii_digit())) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        match self.variant {
+            Some(v) => Item::new(format!("<mn mathvariant=\"{}\">{}</mn>", v, digits)),
+            None => Item::new(format!("<mn>{}</mn>", digits))
+        }
+    }
+
+    // what follows `^`, `_`, `\frac` and so on: a group or a single character
+    fn parse_argument(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                match self.parse_row()? {
+                    (items, Stop::Close) => Ok(mrow(items)),
+                    _ => Err("a { is never closed".to_string())
+                }
+            },
+            Some('\\') => {
+                self.pos += 1;
+                let name = self.read_command()?;
+                Ok(self.parse_command(&name)?.html)
+            },
+            Some(c) if c.is_ascii_digit() => {
+                self.pos += 1;
+                Ok(format!("<mn>{}</mn>", c))
+            },
+            Some('}') | None => Err("missing argument".to_string()),
+            Some(_) => Ok(self.parse_atom()?.html)
+        }
+    }
+
+    fn parse_delimiter(&mut self) -> Result<String, String> {
+        match self.next_char() {
+            Some('.') => Ok(String::new()),
+            Some('\\') => {
+                let name = self.read_command()?;
+                match name.as_str() {
+                    "{" | "}" | "|" => Ok(name.replace('|', "‖")),
+                    _ => match lookup(OPERATORS, &name) {
+                        Some(op) => Ok(op.to_string()),
+                        None => Err(format!("\\{} is not a delimiter", name))
+                    }
+                }
+            },
+            Some(c) => Ok(c.to_string()),
+            None => Err("missing delimiter".to_string())
+        }
+    }
+
+    fn parse_environment(&mut self, name: &str) -> Result<Item, String> {
+        let (open, close, align) = match name {
+            "matrix" | "smallmatrix" => ("", "", "center"),
+            "pmatrix" => ("(", ")", "center"),
+            "bmatrix" => ("[", "]", "center"),
+            "Bmatrix" => ("{", "}", "center"),
+            "vmatrix" => ("|", "|", "center"),
+            "Vmatrix" => ("‖", "‖", "center"),
+            "cases" => ("{", "", "left"),
+            "array" => {
+                self.read_raw_group()?;  // the column layout
+                ("", "", "center")
+            },
+            "aligned" | "align" | "align*" | "split" => ("", "", "right left"),
+            "gathered" | "gather" | "gather*" => ("", "", "center"),
+            _ => { return Err(format!("unknown environment {}", name)); }
+        };
+        let mut rows = vec![];
+        let mut cells = vec![];
+        loop {
+            let (items, stop) = self.parse_row()?;
+            cells.push(format!("<mtd>{}</mtd>", mrow(items)));
+            match stop {
+                Stop::Align => (),
+                Stop::NewLine => {
+                    rows.push(cells);
+                    cells = vec![];
+                },
+                Stop::End(ref end) if end == name => {
+                    // ignore the empty row after a trailing `\\`
+                    if cells.len() > 1 || cells[0] != "<mtd></mtd>" {
+                        rows.push(cells);
+                    }
+                    break;
+                },
+                _ => { return Err(format!("\\begin{{{}}} is never ended", name)); }
+            }
+        }
+        let mut table = format!("<mtable columnalign=\"{}\">", align);
+        for row in rows {
+            table.push_str("<mtr>");
+            for cell in row {
+                table.push_str(&cell);
+            }
+            table.push_str("</mtr>");
+        }
+        table.push_str("</mtable>");
+        Ok(Item::new(format!("<mrow>{}{}{}</mrow>", fence(open), table, fence(close))))
+    }
+
+    fn parse_command(&mut self, name: &str) -> Result<Item, String> {
+        if let Some(s) = lookup(GREEK, name) {
+            return Ok(self.identifier(s));
+        }
+        if let Some(s) = lookup(UPPER_GREEK, name) {
+            return Ok(Item::new(format!("<mi mathvariant=\"normal\">{}</mi>", s)));
+        }
+        if let Some(s) = lookup(IDENTIFIERS, name) {
+            return Ok(Item::new(format!("<mi>{}</mi>", s)));
+        }
+        if let Some(s) = lookup(OPERATORS, name) {
+            return Ok(operator(s));
+        }
+        if let Some(s) = lookup(BIG_OPERATORS, name) {
+            return Ok(Item{html: format!("<mo>{}</mo>", s), kind: Kind::Limits});
+        }
+        if let Some(s) = lookup(INTEGRALS, name) {
+            return Ok(operator(s));
+        }
+        if FUNCTIONS.contains(&name) {
+            return Ok(Item::new(format!("<mi>{}</mi>", name)));
+        }
+        if LIMIT_FUNCTIONS.contains(&name) {
+            return Ok(Item{html: format!("<mi>{}</mi>", name), kind: Kind::Limits});
+        }
+        if let Some(s) = lookup(ACCENTS, name) {
+            let base = self.parse_argument()?;
+            return Ok(Item::new(format!(
+                "<mover accent=\"true\">{}<mo>{}</mo></mover>", base, s)));
+        }
+        if let Some(s) = lookup(UNDER_ACCENTS, name) {
+            let base = self.parse_argument()?;
+            return Ok(Item::new(format!(
+                "<munder accentunder=\"true\">{}<mo>{}</mo></munder>", base, s)));
+        }
+        if let Some(v) = lookup(VARIANTS, name) {
+            let previous = self.variant;
+            self.variant = Some(v);
+            let argument = self.parse_argument();
+            self.variant = previous;
+            return Ok(Item::new(argument?));
+        }
+        if let Some(width) = lookup(SPACES, name) {
+            return Ok(Item::new(format!("<mspace width=\"{}\"/>", width)));
+        }
+        match name {
+            "{" | "}" | "|" => Ok(operator(&name.replace('|', "‖"))),
+            "$" | "%" | "&" | "#" | "_" => Ok(self.identifier(name)),
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.parse_argument()?;
+                let denominator = self.parse_argument()?;
+                Ok(Item::new(format!("<mfrac>{}{}</mfrac>", numerator, denominator)))
+            },
+            "binom" => {
+                let top = self.parse_argument()?;
+                let bottom = self.parse_argument()?;
+                Ok(Item::new(format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                    top, bottom)))
+            },
+            "sqrt" => {
+                if self.peek() == Some('[') {
+                    self.pos += 1;
+                    let start = self.pos;
+                    while self.pos < self.chars.len() && self.chars[self.pos] != ']' {
+                        self.pos += 1;
+                    }
+                    let index: String = self.chars[start..self.pos].iter().collect();
+                    self.pos += 1;
+                    let index = MathParser::new(&index, false).parse_all()?;
+                    let base = self.parse_argument()?;
+                    Ok(Item::new(format!("<mroot>{}{}</mroot>", base, index)))
+                } else {
+                    Ok(Item::new(format!("<msqrt>{}</msqrt>", self.parse_argument()?)))
+                }
+            },
+            "text" | "textrm" | "textit" | "textbf" | "mbox" => {
+                let text = self.read_raw_group()?;
+                Ok(Item::new(format!("<mtext>{}</mtext>", escape(&text))))
+            },
+            "operatorname" => {
+                let text = self.read_raw_group()?;
+                Ok(Item::new(format!("<mi>{}</mi>", escape(text.trim()))))
+            },
+            "left" => {
+                let open = self.parse_delimiter()?;
+                let (items, stop) = self.parse_row()?;
+                if stop != Stop::Right {
+                    return Err("\\left without \\right".to_string());
+                }
+                let close = self.parse_delimiter()?;
+                let mut html = format!("<mrow>{}", fence(&open));
+                for item in items {
+                    html.push_str(&item.html);
+                }
+                html.push_str(&fence(&close));
+                html.push_str("</mrow>");
+                Ok(Item::new(html))
+            },
+            "begin" => {
+                let environment = self.read_raw_group()?;
+                self.parse_environment(environment.trim())
+            },
+            _ if IGNORED.contains(&name) => Ok(Item::new(String::new())),
+            _ => Err(format!("unknown command \\{}", name))
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Item, String> {
+        let c = match self.peek() {
+            Some(c) => c,
+            None => { return Err("unexpected end".to_string()); }
+        };
+        if c.is_ascii_digit() {
+            return Ok(self.number());
+        }
+        self.pos += 1;
+        match c {
+            '\\' => {
+                let name = self.read_command()?;
+                self.parse_command(&name)
+            },
+            '{' => match self.parse_row()? {
+                (items, Stop::Close) => Ok(Item::new(mrow(items))),
+                _ => Err("a { is never closed".to_string())
+            },
+            '-' => Ok(operator("−")),
+            '*' => Ok(operator("∗")),
+            '\'' => Ok(operator("′")),
+            '~' => Ok(Item::new("<mspace width=\"0.333em\"/>".to_string())),
+            c if c.is_alphabetic() => Ok(self.identifier(&c.to_string())),
+            c => Ok(operator(&c.to_string()))
+        }
+    }
+
+    // attach any sub- and superscripts, and primes, to `base`
+    fn parse_scripts(&mut self, base: Item) -> Result<Item, String> {
+        let mut sub = None;
+        let mut sup = None;
+        let mut primes = String::new();
+        loop {
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.parse_argument()?);
+                },
+                Some('^') if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.parse_argument()?);
+                },
+                Some('\'') => {
+                    self.pos += 1;
+                    primes.push('′');
+                },
+                Some('_') | Some('^') => {
+                    return Err("double subscript or superscript".to_string());
+                },
+                _ => break
+            }
+        }
+        if !primes.is_empty() {
+            sup = Some(match sup {
+                Some(s) => format!("<mrow><mo>{}</mo>{}</mrow>", primes, s),
+                None => format!("<mo>{}</mo>", primes)
+            });
+        }
+        let limits = base.kind == Kind::Limits && self.display;
+        let html = match (sub, sup) {
+            (None, None) => { return Ok(base); },
+            (Some(b), None) if limits => format!("<munder>{}{}</munder>", base.html, b),
+            (None, Some(p)) if limits => format!("<mover>{}{}</mover>", base.html, p),
+            (Some(b), Some(p)) if limits => format!(
+                "<munderover>{}{}{}</munderover>", base.html, b, p),
+            (Some(b), None) => format!("<msub>{}{}</msub>", base.html, b),
+            (None, Some(p)) => format!("<msup>{}{}</msup>", base.html, p),
+            (Some(b), Some(p)) => format!("<msubsup>{}{}{}</msubsup>", base.html, b, p)
+        };
+        Ok(Item::new(html))
+    }
+
+    fn parse_row(&mut self) -> Result<(Vec<Item>, Stop), String> {
+        let mut items = vec![];
+        loop {
+            let base = match self.peek() {
+                None => { return Ok((items, Stop::Eof)); },
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok((items, Stop::Close));
+                },
+                Some('&') => {
+                    self.pos += 1;
+                    return Ok((items, Stop::Align));
+                },
+                // scripts without a base, as in `{}^{14}C`
+                Some('_') | Some('^') => Item::new("<mrow></mrow>".to_string()),
+                Some('\\') => {
+                    let start = self.pos;
+                    self.pos += 1;
+                    match self.read_command()?.as_str() {
+                        "\\" => { return Ok((items, Stop::NewLine)); },
+                        "right" => { return Ok((items, Stop::Right)); },
+                        "end" => {
+                            let name = self.read_raw_group()?;
+                            return Ok((items, Stop::End(name.trim().to_string())));
+                        },
+                        _ => {
+                            self.pos = start;
+                            self.parse_atom()?
+                        }
+                    }
+                },
+                Some(_) => self.parse_atom()?
+            };
+            let item = self.parse_scripts(base)?;
+            if !item.html.is_empty() {
+                items.push(item);
+            }
+        }
+    }
+
+    fn parse_all(&mut self) -> Result<String, String> {
+        match self.parse_row()? {
+            (items, Stop::Eof) => Ok(mrow(items)),
+            (_, Stop::Close) => Err("a } is never opened".to_string()),
+            (_, Stop::Right) => Err("\\right without \\left".to_string()),
+            (_, Stop::End(name)) => Err(format!("\\end{{{}}} without \\begin", name)),
+            (_, _) => Err("& and \\\\ only work inside environments like aligned".to_string())
+        }
+    }
+}
+
+
+/// Render LaTeX math, the contents of `$...$` or `$$...$$`, to MathML,
+/// which browsers display without any scripts. Covers the common commands
+/// and environments; anything else is an error rather than wrong output.
+pub fn latex_to_mathml(tex: &str, display: bool) -> Result<String, SidenoteError> {
+    let body = match MathParser::new(tex, display).parse_all() {
+        Ok(b) => b,
+        Err(e) => { return Err(SidenoteError::Math(format!("{} in '{}'", e, tex.trim()))); }
+    };
+    if display {
+        Ok(format!("<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\">{}</math>",
+                   body))
+    } else {
+        Ok(format!("<math xmlns=\"http://www.w3.org/1998/Math/MathML\">{}</math>", body))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::latex_to_mathml;
+
+    fn inline(tex: &str) -> String {
+        let math = latex_to_mathml(tex, false).expect("Can't render math");
+        math["<math xmlns=\"http://www.w3.org/1998/Math/MathML\">".len()..math.len() - 7]
+            .to_string()
+    }
+
+    #[test]
+    fn can_render_math() {
+        assert_eq!(inline("E = mc^2"),
+                   "<mrow><mi>E</mi><mo>=</mo><mi>m</mi><msup><mi>c</mi><mn>2</mn></msup></mrow>");
+        assert_eq!(inline("x_{i+1}"),
+                   "<msub><mi>x</mi><mrow><mi>i</mi><mo>+</mo><mn>1</mn></mrow></msub>");
+        assert_eq!(inline("\\frac{1}{\\sqrt{2}}"),
+                   "<mfrac><mn>1</mn><msqrt><mn>2</mn></msqrt></mfrac>");
+        assert_eq!(inline("\\alpha \\leq 3.14"),
+                   "<mrow><mi>α</mi><mo>≤</mo><mn>3.14</mn></mrow>");
+        assert_eq!(inline("f'(x)"),
+                   "<mrow><msup><mi>f</mi><mo>′</mo></msup><mo>(</mo><mi>x</mi><mo>)</mo></mrow>");
+        assert_eq!(inline("\\mathbb{R}"), "<mi mathvariant=\"double-struck\">R</mi>");
+        assert_eq!(inline("\\text{if } x < 0"),
+                   "<mrow><mtext>if </mtext><mi>x</mi><mo>&lt;</mo><mn>0</mn></mrow>");
+        assert_eq!(inline("\\sum_{i=1}^n i"),
+                   "<mrow><msubsup><mo>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></msubsup><mi>i</mi></mrow>");
+    }
+
+    #[test]
+    fn can_render_display_math() {
+        let math = latex_to_mathml("\\sum_{i=1}^n i", true).unwrap();
+        assert!(math.contains("display=\"block\""));
+        assert!(math.contains("<munderover><mo>∑</mo>"));
+        let matrix = latex_to_mathml("\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}", true)
+            .unwrap();
+        assert!(matrix.contains("<mtr><mtd><mi>a</mi></mtd><mtd><mi>b</mi></mtd></mtr>"));
+        assert!(matrix.contains("<mo fence=\"true\" stretchy=\"true\">(</mo>"));
+        let fenced = inline("\\left( \\frac{a}{b} \\right)");
+        assert!(fenced.starts_with("<mrow><mo fence=\"true\" stretchy=\"true\">(</mo><mfrac>"));
+    }
+
+    #[test]
+    fn catch_math_errors() {
+        assert!(latex_to_mathml("\\frac{a}", false).is_err());
+        assert!(latex_to_mathml("{a", false).is_err());
+        assert!(latex_to_mathml("a}", false).is_err());
+        assert!(latex_to_mathml("\\notacommand", false).is_err());
+        assert!(latex_to_mathml("\\left( a", false).is_err());
+        assert!(latex_to_mathml("x^2^3", false).is_err());
+        assert!(latex_to_mathml("\\begin{matrix} a", false).is_err());
+    }
+}
@@ -3,21 +3,39 @@ use std::borrow::Cow;
 use std::time::SystemTime;
 use handlebars::{Handlebars, html_escape};
 
-use sidenote_error::SidenoteError;
+use sidenote_error::{SidenoteError, Position};
+use highlight::highlight;
+use sidenotes::sidenote_id;
+use front_matter::{split_front_matter, FrontMatter};
 use toc::IndexedBlogPost;
+use footnotes::{collect_footnotes, markdown_parser, Footnotes};
+use headings::{Heading, nest_headings};
+use variables::{Variables, add_front_matter};
 
 
 pub struct SidenoteParser<'a> {
     parser: Parser<'a>,
+    source: &'a str,
+    line_offset: usize,
+    pub span: (usize, usize),
     link_prefix: String,
     pub in_code_block: bool,
     pub in_sidenote_block: bool,
+    pub in_margin_note: bool,
+    pub sidenote_start: usize,
+    pub margin_notes: usize,
     pub remaining_text: String,
     pub title: &'a mut Option<String>,
     pub in_title: bool,
     pub in_image: bool,
     pub remaining_events: Vec<Event<'a>>,
-    pub sidenotes: &'a mut Vec<String>
+    pub sidenotes: &'a mut Vec<String>,
+    pub headings: &'a mut Vec<Heading>,
+    pub as_footnotes: bool,
+    pub escaped: bool,
+    pub lookahead: Vec<(Event<'a>, usize, usize)>,
+    pub footnotes: Footnotes<'a>,
+    pub variables: Variables
 }
 
 
@@ -30,22 +48,39 @@ pub struct SidenoteParser<'a> {
 /// * checks text events for sidenotes, 
 /// * checks code block tags, and remembers if we're in a 
 /// code block, so as not to parse for sidenotes in that case
+/// * gives the other headings ids, for the table of contents
+/// * turns images on their own into figures
+/// * substitutes template variables like `{{post_url}}`
+/// * renders `$...$` and `$$...$$` math to MathML
 /// * returns the other events unchanged.
 impl<'a> SidenoteParser<'a> {
     pub fn new(parser: Parser<'a>, 
                title: &'a mut Option<String>, 
-               sidenotes: &'a mut Vec<String>) -> SidenoteParser<'a> {
+               sidenotes: &'a mut Vec<String>,
+               headings: &'a mut Vec<Heading>) -> SidenoteParser<'a> {
         SidenoteParser{
             parser,
+            source: "",
+            line_offset: 0,
+            span: (0, 0),
             title,
             link_prefix: "".to_string(),
             in_code_block: false,
             in_sidenote_block: false,
+            in_margin_note: false,
+            sidenote_start: 0,
+            margin_notes: 0,
             remaining_text: String::from(""),
             in_title: false,
             in_image: false,
             remaining_events: vec![],
-            sidenotes
+            sidenotes,
+            headings,
+            as_footnotes: false,
+            escaped: false,
+            lookahead: vec![],
+            footnotes: Footnotes::new(),
+            variables: Variables::new()
         }
     }
 
@@ -53,10 +88,72 @@ impl<'a> SidenoteParser<'a> {
         self.link_prefix = link_prefix;
     }
 
+    /// the markdown being parsed, to look up the source of events and
+    /// footnote definitions. It starts after `line_offset` lines of front
+    /// matter.
+    pub fn set_source(&mut self, source: &'a str, line_offset: usize) {
+        self.source = source;
+        self.line_offset = line_offset;
+        self.footnotes = collect_footnotes(source);
+    }
+
+    /// the line and column of the byte `offset` of the source, for errors
+    pub fn source_position(&self, offset: usize) -> Position {
+        let mut position = Position::new(self.source, offset);
+        position.line += self.line_offset;
+        position
+    }
+
+    /// the offset of `remaining_text` in the source, which it ends
+    pub fn remaining_offset(&self) -> usize {
+        self.span.1.saturating_sub(self.remaining_text.len())
+    }
+
+    /// the offsets of the current event, set when reading ahead too
+    pub fn set_span(&mut self, start: usize, end: usize) {
+        self.span = (start, end);
+    }
+
+    /// the source of the markdown between two offsets
+    pub fn raw(&self, start: usize, end: usize) -> &'a str {
+        self.source.get(start..end).unwrap_or("")
+    }
+
+    /// the next event from the markdown parser together with its offsets
+    /// in the source, first handing back any events we read ahead
+    pub fn next_raw_event(&mut self) -> Option<(Event<'a>, usize, usize)> {
+        match self.lookahead.pop() {
+            Some(e) => Some(e),
+            None => {
+                let start = self.parser.get_offset();
+                let event = self.parser.next()?;
+                Some((event, start, self.parser.get_offset()))
+            }
+        }
+    }
+
+    // pulldown_cmark gives escaped characters like `\$` events of their
+    // own, which differ from their source
+    fn next_event(&mut self) -> Option<Event<'a>> {
+        let (event, start, end) = self.next_raw_event()?;
+        self.span = (start, end);
+        self.escaped = match event {
+            Event::Text(ref text) => !self.source.is_empty() && self.raw(start, end) != text,
+            _ => false
+        };
+        Some(event)
+    }
+
+    // inline code may be in a sidenote, code blocks may not
+    fn parse_inline_code_tag(&mut self, start: bool, tag: Event<'a>) -> Event<'a> {
+        self.in_code_block = start;
+        tag
+    }
+
     fn parse_code_tag(&mut self, start: bool, on_success_return: Event<'a>) -> 
         Result<Event<'a>, SidenoteError> {
         if self.in_sidenote_block {
-            Err(SidenoteError::NotMatched)
+            Err(SidenoteError::NotMatched(self.source_position(self.span.0)))
         } else {
             self.in_code_block = start;
             Ok(on_success_return)
@@ -64,22 +161,24 @@ impl<'a> SidenoteParser<'a> {
     }
 
     fn parse_paragraph_tag(&mut self, start: bool) -> 
-        Event<'a> {
+        Result<Event<'a>, SidenoteError> {
         if self.in_sidenote_block {
             if start {
-                self.sidenotes.last_mut().unwrap().push_str("\n\n");
-                Event::InlineHtml(Cow::from("<br /><br />\n"))
+                Ok(Event::InlineHtml(Cow::from("<br /><br />\n")))
             } else { // create empty event
-                Event::Text(Cow::from(""))
+                Ok(Event::Text(Cow::from("")))
                 // TODO: would be cleaner to instead skip this and go straight
                 // to the next event and invoke self.next()
                 // but to do this need to change all return types
             }
         } else {
             if start {
-                Event::Start(Tag::Paragraph)
+                match self.parse_figure()? {
+                    Some(figure) => Ok(figure),
+                    None => Ok(Event::Start(Tag::Paragraph))
+                }
             } else {
-                Event::End(Tag::Paragraph)
+                Ok(Event::End(Tag::Paragraph))
             }
         }
     }
@@ -88,11 +187,35 @@ impl<'a> SidenoteParser<'a> {
         Event::InlineHtml(Cow::from("<pre class=\"code\"><code>"))
     }
 
+    // read the rest of the code block straight from the markdown parser
+    // and highlight it in one go. Code blocks in languages we don't know
+    // are only escaped.
+    fn parse_codeblock(&mut self, lang: Cow<'a, str>) -> Result<Event<'a>, SidenoteError> {
+        if self.in_sidenote_block || lang.trim().is_empty() {
+            return self.parse_code_tag(true, SidenoteParser::start_codeblock());
+        }
+        let mut code = String::new();
+        for event in &mut self.parser {
+            match event {
+                Event::End(Tag::CodeBlock(_)) => break,
+                Event::Text(text) => code.push_str(&text),
+                _ => ()
+            }
+        }
+        let highlighted = match highlight(&code, &lang) {
+            Some(h) => h,
+            None => html_escape(&code)
+        };
+        Ok(Event::Html(Cow::from(format!(
+            "<pre class=\"code\"><code class=\"language-{}\">{}</code></pre>\n",
+            html_escape(lang.trim()), highlighted))))
+    }
+
     fn link_is_relative(link: &Cow<str>) -> bool {
         !(link.contains("://") || (link.chars().next() == Some('/')))
     }
 
-    fn rewrite_link<'b>(&'a self, mut link: Cow<'b, str>) -> Cow<'b, str> {
+    pub fn rewrite_link<'b>(&self, mut link: Cow<'b, str>) -> Cow<'b, str> {
         if SidenoteParser::link_is_relative(&link) {
             link.to_mut().insert_str(0, &self.link_prefix);
         }
@@ -104,57 +227,65 @@ impl<'a> SidenoteParser<'a> {
         match event {
             Event::Text(text) => Ok(self.parse_text_block(text)),
             Event::Start(tag) => match tag {
-                Tag::Code => self.parse_code_tag(true, Event::Start(Tag::Code)),
-                Tag::CodeBlock(_lang) => self.parse_code_tag(true, 
-                    SidenoteParser::start_codeblock()),
-                Tag::Paragraph => Ok(self.parse_paragraph_tag(true)),
+                Tag::Code => Ok(self.parse_inline_code_tag(true, Event::Start(Tag::Code))),
+                Tag::CodeBlock(lang) => self.parse_codeblock(lang),
+                Tag::Paragraph => self.parse_paragraph_tag(true),
                 Tag::Header(1) => {
                     self.in_title = true;
                     Ok(Event::Start(Tag::Header(1)))
                 },
+                Tag::Header(level) => Ok(self.start_heading(level)),
                 Tag::Image(url, title) => {
                     self.in_image = true;
+                    let url = self.substitute_variables(url)?;
                     Ok(Event::Start(Tag::Image(self.rewrite_link(url), title)))
                 },
-                Tag::Link(link, title) => 
-                    Ok(Event::Start(Tag::Link(self.rewrite_link(link), title))),
+                Tag::Link(link, title) => {
+                    let link = self.substitute_variables(link)?;
+                    Ok(Event::Start(Tag::Link(self.rewrite_link(link), title)))
+                },
+                Tag::FootnoteDefinition(_) => Ok(self.skip_footnote_definition()),
                 _ => Ok(Event::Start(tag))
             },
             Event::End(tag) => match tag {
-                Tag::Code => self.parse_code_tag(false, Event::End(Tag::Code)),
+                Tag::Code => Ok(self.parse_inline_code_tag(false, Event::End(Tag::Code))),
                 Tag::CodeBlock(lang) => self.parse_code_tag(false, 
                     Event::End(Tag::CodeBlock(lang))),
-                Tag::Paragraph => Ok(self.parse_paragraph_tag(false)),
+                Tag::Paragraph => self.parse_paragraph_tag(false),
                 Tag::Header(1) => {
                     self.in_title = false;
                     Ok(Event::InlineHtml(Cow::from("</h1><section>")))
                 },
+                Tag::Header(level) => Ok(self.end_heading(level)),
                 Tag::Image(url, title) => {
                     self.in_image = false;
                     Ok(Event::End(Tag::Image(url, title)))
                 },
                 Tag::Link(link, title) => 
                     Ok(Event::End(Tag::Link(link, title))),
+                // closes a footnote read as a sidenote
+                Tag::FootnoteDefinition(_) => Ok(self.close_sidenote()),
                 _ => Ok(Event::End(tag))
             },
+            Event::FootnoteReference(name) => self.parse_footnote_reference(name),
+            Event::Html(html) => Ok(Event::Html(self.substitute_variables(html)?)),
+            Event::InlineHtml(html) => Ok(Event::InlineHtml(self.substitute_variables(html)?)),
             _ => Ok(event)
         }
     }
 } 
 
 
-impl<'a> Iterator for SidenoteParser<'a> {
-    type Item = Result<Event<'a>, SidenoteError>;
+impl<'a> SidenoteParser<'a> {
 
-    fn next(&mut self) -> Option<Result<Event<'a>, SidenoteError>> {
+    pub fn next_parsed(&mut self) -> Option<Result<Event<'a>, SidenoteError>> {
         match self.remaining_events.pop() {
             Some(e) => Some(Ok(e)),
             None => {
                 if self.remaining_text.len() > 0 {
                     Some(self.parse_remaining_text())
                 } else {
-                    let next_event = self.parser.next();
-                    match next_event {
+                    match self.next_event() {
                         Some(event) => Some(self.parse_next_event(event)),
                         None => None
                     }
@@ -162,17 +293,44 @@ impl<'a> Iterator for SidenoteParser<'a> {
             }
         }
     }
+}
+
+
+impl<'a> Iterator for SidenoteParser<'a> {
+    type Item = Result<Event<'a>, SidenoteError>;
+
+    /// the events between the braces of a sidenote are also rendered
+    /// into the sidenote's own HTML, for the list of footnotes. A sidenote
+    /// still open at the end of the markdown is an error.
+    fn next(&mut self) -> Option<Result<Event<'a>, SidenoteError>> {
+        let was_in_sidenote = self.in_sidenote_block && !self.in_margin_note;
+        let next = self.next_parsed();
+        if next.is_none() && self.in_sidenote_block {
+            self.in_sidenote_block = false;
+            return Some(Err(SidenoteError::NotMatched(self.source_position(self.sidenote_start))));
+        }
+        if was_in_sidenote && self.in_sidenote_block {
+            if let Some(Ok(ref event)) = next {
+                html::push_html(self.sidenotes.last_mut().unwrap(),
+                                vec![event.clone()].into_iter());
+            }
+        }
+        next
+    }
 } 
 
 
+/// a sidenote as listed for the post template, with the id of its
+/// checkbox in the article
 #[derive(Serialize)]
 struct Sidenote {
+    id: String,
     note: String
 }
 
-impl From<String> for Sidenote {
-    fn from(note: String) -> Self {
-        Sidenote{note}
+impl From<(usize, String)> for Sidenote {
+    fn from(a: (usize, String)) -> Self {
+        Sidenote{id: sidenote_id(a.0 + 1), note: a.1}
     }
 }
 
@@ -185,7 +343,9 @@ pub struct PostData<'a> {
     last_updated: SystemTime,
     index_url: String,
     post_url: String,
-    sidenotes: Vec<Sidenote>
+    sidenotes: Vec<Sidenote>,
+    toc: Vec<Heading>,
+    meta: FrontMatter
 }
 
 
@@ -198,7 +358,9 @@ impl<'a> PostData<'a> {
             last_updated: SystemTime::now(),
             index_url: "/".to_string(),
             post_url: "/".to_string(),
-            sidenotes: vec![]
+            sidenotes: vec![],
+            toc: vec![],
+            meta: FrontMatter::default()
         }
     }
 
@@ -216,9 +378,11 @@ impl<'a, 'b, 'c> From<(&'a str,
                        &'b mut IndexedBlogPost, 
                        &'c str, 
                        String, 
-                       Vec<String>)> for PostData<'a> {
+                       Vec<String>,
+                       Vec<Heading>)> for PostData<'a> {
 
-    fn from(a: (&'a str, &'b mut IndexedBlogPost, &'c str, String, Vec<String>)) -> Self {
+    fn from(a: (&'a str, &'b mut IndexedBlogPost, &'c str, String, Vec<String>,
+                Vec<Heading>)) -> Self {
         PostData{
             article: a.0,
             first_published: a.1.first_published,
@@ -230,8 +394,11 @@ impl<'a, 'b, 'c> From<(&'a str,
             },
             post_url: a.3,
             sidenotes: a.4.into_iter()
+                .enumerate()
                 .map(Sidenote::from)
-                .collect()
+                .collect(),
+            toc: nest_headings(a.5),
+            meta: a.1.meta.clone()
         }
     }
 }
@@ -241,26 +408,138 @@ impl<'a, 'b, 'c> From<(&'a str,
 pub struct ParsedMarkdown {
     pub html: String,
     pub title: Option<String>,
-    pub sidenotes: Vec<String>
+    pub sidenotes: Vec<String>,
+    pub headings: Vec<Heading>,
+    pub front_matter: FrontMatter,
+    pub excerpt: String
 }
 
 
-/// Main function to convert markdown to html
+const EXCERPT_LENGTH: usize = 300;
+
+
+// cut the excerpt at a word boundary
+fn truncate_excerpt(excerpt: &str) -> String {
+    let excerpt = excerpt.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if excerpt.chars().count() <= EXCERPT_LENGTH {
+        return excerpt;
+    }
+    let mut truncated = String::new();
+    for word in excerpt.split(' ') {
+        if truncated.chars().count() + word.chars().count() + 1 > EXCERPT_LENGTH {
+            break;
+        }
+        if !truncated.is_empty() {
+            truncated.push(' ');
+        }
+        truncated.push_str(word);
+    }
+    truncated.push('…');
+    truncated
+}
+
+
+/// Main function to convert markdown to html.
+/// The front matter is stripped before parsing. A title given in the
+/// front matter takes precedence over the first header.
 pub fn html_from_markdown(md: &str, link_prefix: String) -> Result<ParsedMarkdown, SidenoteError> {
+    parse_markdown(md, link_prefix, false, &Variables::new())
+}
+
+
+/// Convert markdown to html for feeds, where there are no margins:
+/// sidenotes become numbered footnotes, listed at the end of the article.
+pub fn feed_html_from_markdown(md: &str, link_prefix: String) -> Result<ParsedMarkdown, SidenoteError> {
+    parse_markdown(md, link_prefix, true, &Variables::new())
+}
+
+
+/// like `html_from_markdown`, with the template `variables` available in
+/// the markdown besides the front matter
+pub fn html_from_markdown_with_variables(md: &str, link_prefix: String, variables: &Variables)
+    -> Result<ParsedMarkdown, SidenoteError> {
+    parse_markdown(md, link_prefix, false, variables)
+}
+
+
+/// like `feed_html_from_markdown`, with the template `variables`
+/// available in the markdown besides the front matter
+pub fn feed_html_from_markdown_with_variables(md: &str, link_prefix: String, variables: &Variables)
+    -> Result<ParsedMarkdown, SidenoteError> {
+    parse_markdown(md, link_prefix, true, variables)
+}
+
+
+fn parse_markdown(md: &str, link_prefix: String, as_footnotes: bool, variables: &Variables) 
+    -> Result<ParsedMarkdown, SidenoteError> {
+    let (front_matter, body) = split_front_matter(md)?;
+    let line_offset = md[..md.len() - body.len()].matches('\n').count();
+    let md = body;
     let mut title: Option<String> = None;
     let mut article = "<article>".to_string();
     let mut sidenotes: Vec<String> = vec![];
+    let mut headings: Vec<Heading> = vec![];
+    let mut excerpt = String::new();
     {
-        let mut parser = SidenoteParser::new(Parser::new(md), &mut title, &mut sidenotes);
+        let mut parser = SidenoteParser::new(markdown_parser(md), &mut title, &mut sidenotes,
+                                             &mut headings);
+        parser.set_source(md, line_offset);
         parser.set_link_prefix(link_prefix);
-        for event in parser {
-            html::push_html(&mut article, vec![event?].into_iter());
+        parser.as_footnotes = as_footnotes;
+        parser.variables = variables.clone();
+        add_front_matter(&mut parser.variables, &front_matter);
+        let mut in_paragraph = false;
+        let mut after_sidenote = false;
+        loop {
+            let was_in_sidenote = parser.in_sidenote_block;
+            let was_in_margin_note = parser.in_margin_note;
+            let event = match parser.next() {
+                Some(e) => e?,
+                None => break
+            };
+            if was_in_sidenote || parser.in_sidenote_block {
+                // the sidenote is left out of the excerpt
+                after_sidenote = true;
+            }
+            if was_in_sidenote {
+                if as_footnotes && !was_in_margin_note {
+                    continue;  // the sidenote is listed at the end instead
+                }
+            } else {
+                match event {
+                    Event::Start(Tag::Paragraph) => in_paragraph = true,
+                    Event::End(Tag::Paragraph) => {
+                        in_paragraph = false;
+                        excerpt.push(' ');
+                    },
+                    Event::Text(ref text) if in_paragraph && !parser.in_image
+                        && excerpt.len() < 2 * EXCERPT_LENGTH => {
+                        // no space before the punctuation following a sidenote
+                        if after_sidenote && text.starts_with(|c| ".,;:!?)".contains(c)) {
+                            let trimmed = excerpt.trim_end().len();
+                            excerpt.truncate(trimmed);
+                        }
+                        after_sidenote = false;
+                        excerpt.push_str(text);
+                    },
+                    Event::SoftBreak => excerpt.push(' '),
+                    _ => ()
+                }
+            }
+            html::push_html(&mut article, vec![event].into_iter());
         }
     }
 
+    if as_footnotes && !sidenotes.is_empty() {
+        article.push_str("<ol class=\"footnotes\">");
+        for note in sidenotes.iter() {
+            article.push_str(&format!("<li>{}</li>", note.trim()));
+        }
+        article.push_str("</ol>");
+    }
     article.push_str("</section></article>");
 
-    let title = match title {
+    let title = match front_matter.title.clone().or(title) {
         Some(t) => match t.len() {
             0 => None,  // don't allow empty titles
             _ => Some(t)
@@ -268,7 +547,8 @@ pub fn html_from_markdown(md: &str, link_prefix: String) -> Result<ParsedMarkdow
         None => None
     };
 
-    Ok(ParsedMarkdown{html: article, title, sidenotes})
+    Ok(ParsedMarkdown{html: article, title, sidenotes, headings, front_matter,
+                      excerpt: truncate_excerpt(&excerpt)})
 
 } 
 
@@ -277,7 +557,12 @@ pub fn html_from_markdown(md: &str, link_prefix: String) -> Result<ParsedMarkdow
 mod tests {
     use std::borrow::Cow;
     use pulldown_cmark::Parser;
-    use super::{html_from_markdown, SidenoteParser};
+    use serde_json;
+    use toc::IndexedBlogPost;
+    use sidenote_error::SidenoteError;
+    use super::{html_from_markdown, feed_html_from_markdown, html_from_markdown_with_variables,
+                truncate_excerpt, SidenoteParser, PostData};
+    use variables::Variables;
 
     #[test]
     fn check_catch_sidenote_errors() {
@@ -296,6 +581,30 @@ Here is some text with { badly formatted {sidenotes}.
         assert!(html_buf.is_err());
     }
 
+    #[test]
+    fn can_locate_sidenote_errors() {
+        let md = "---\ntitle: errors\n---\nSome text{with a note}.\n\nA {nested {note}}.\n";
+        match html_from_markdown(md, "".to_string()) {
+            Err(SidenoteError::Nested(p)) => {
+                assert_eq!((p.line, p.column), (6, 11));
+                assert_eq!(p.snippet, "A {nested {note}}.");
+            },
+            other => panic!("Expected a nesting error, got {:?}", other.map(|p| p.html))
+        }
+        match html_from_markdown("Fine $x$ and\n  then} not.", "".to_string()) {
+            Err(SidenoteError::NotMatched(p)) => assert_eq!((p.line, p.column), (2, 7)),
+            other => panic!("Expected a matching error, got {:?}", other.map(|p| p.html))
+        }
+        match html_from_markdown("One\n\nTwo {unclosed\n\nthree", "".to_string()) {
+            Err(SidenoteError::NotMatched(p)) => assert_eq!((p.line, p.column), (3, 5)),
+            other => panic!("Expected a matching error, got {:?}", other.map(|p| p.html))
+        }
+        match html_from_markdown("Code {in `a}` note", "".to_string()) {
+            Err(SidenoteError::NotMatched(p)) => assert_eq!((p.line, p.column), (1, 6)),
+            other => panic!("Expected a matching error, got {:?}", other.map(|p| p.html))
+        }
+    }
+
     #[test]
     fn check_fail_nested_code_sidenote() {
 
@@ -303,8 +612,8 @@ Here is some text with { badly formatted {sidenotes}.
 hello
 =====
 
-Here is some text with { a sidenote `and code nested`
-    }"#;
+Here is some text with { a sidenote `and code nested }`
+"#;
 
         assert!(html_from_markdown(markdown_str, "".to_string()).is_err());
     }
@@ -325,6 +634,56 @@ Here is some text with ` code {and curly braces nested`
     }
 
 
+    #[test]
+    fn can_highlight_code_blocks() {
+        let markdown_str = r#"
+hello
+=====
+
+```rust
+let braces = {1};
+```
+
+```unknown
+a < {b}
+```
+"#;
+        let html = html_from_markdown(markdown_str, "".to_string()).expect("Should succeed").html;
+        assert!(html.contains("<pre class=\"code\"><code class=\"language-rust\"><span class=\"hl-source hl-rust\">"));
+        assert!(html.contains("<pre class=\"code\"><code class=\"language-unknown\">a &lt; {b}\n</code></pre>"));
+        assert!(!html.contains("sidenote"));
+    }
+
+
+    #[test]
+    fn can_render_math() {
+        let markdown_str = r#"
+hello
+=====
+
+Inline $a*b*c_{\{i\}}$ math{with $x^2$ in a sidenote} and `$c$`
+and $$
+\frac{a}{b}
+$$
+
+From $5 to $10.
+
+An escaped \$x$ sign.
+"#;
+        let parsed = html_from_markdown(markdown_str, "".to_string()).expect("Should succeed");
+        assert_eq!(parsed.html, r#"<article>
+<h1>hello</h1><section>
+<p>Inline <math xmlns="http://www.w3.org/1998/Math/MathML"><mrow><mi>a</mi><mo>∗</mo><mi>b</mi><mo>∗</mo><msub><mi>c</mi><mrow><mo>{</mo><mi>i</mi><mo>}</mo></mrow></msub></mrow></math> math<label for="sn-1" id="sn-1-ref" class="margin-toggle sidenote-number"></label><input type="checkbox" id="sn-1" class="margin-toggle"/><span class="sidenote">with <math xmlns="http://www.w3.org/1998/Math/MathML"><msup><mi>x</mi><mn>2</mn></msup></math> in a sidenote</span> and <code>$c$</code>
+and <math xmlns="http://www.w3.org/1998/Math/MathML" display="block"><mfrac><mi>a</mi><mi>b</mi></mfrac></math></p>
+<p>From $5 to $10.</p>
+<p>An escaped $x$ sign.</p>
+</section></article>"#);
+        assert_eq!(parsed.sidenotes, vec![
+            "with <math xmlns=\"http://www.w3.org/1998/Math/MathML\"><msup><mi>x</mi><mn>2</mn></msup></math> in a sidenote"]);
+        assert!(html_from_markdown("Bad $\\frac{a}$ math", "".to_string()).is_err());
+    }
+
+
     #[test]
     fn check_multi_line_sidenotes() {
         let markdown_str = r#"
@@ -344,17 +703,15 @@ spanning multiple lines, which is also supported
 
         let html_buf = html_from_markdown(markdown_str, "".to_string()).expect("Should succeed");
 
-        assert_eq!(html_buf.sidenotes, vec![" a sidenote 
-
-spanning multiple lines, which is also supported 
-
- "]);
+        assert_eq!(html_buf.sidenotes, vec![" a sidenote<br /><br />
+spanning multiple lines, which is also supported<br /><br />
+"]);
 
         assert_eq!(
             html_buf.html,
             r#"<article>
 <h1>hello</h1><section>
-<p>Here is some text with <label class="sidenote-number"></label><span class="sidenote"> a sidenote<br /><br />
+<p>Here is some text with <label for="sn-1" id="sn-1-ref" class="margin-toggle sidenote-number"></label><input type="checkbox" id="sn-1" class="margin-toggle"/><span class="sidenote"> a sidenote<br /><br />
 spanning multiple lines, which is also supported<br /><br />
 </span>.</p>
 <ul>
@@ -365,6 +722,140 @@ spanning multiple lines, which is also supported<br /><br />
         );
     }
 
+    #[test]
+    fn can_keep_sidenote_formatting() {
+        let markdown_str = "Text{with *emphasis*, **strong** text and a [link](other-post/)}.";
+        let parsed = html_from_markdown(markdown_str, "/blog/".to_string()).expect("Should succeed");
+        assert_eq!(parsed.sidenotes, vec![
+            "with <em>emphasis</em>, <strong>strong</strong> text and a <a href=\"/blog/other-post/\">link</a>"]);
+        let parsed = html_from_markdown("a {b `code {x} < y` c}", "".to_string()).expect("Should succeed");
+        assert_eq!(parsed.sidenotes, vec!["b <code>code {x} &lt; y</code> c"]);
+        assert!(parsed.html.contains(r#"<span class="sidenote">b <code>code {x} &lt; y</code> c</span>"#));
+    }
+
+    #[test]
+    fn can_render_margin_notes() {
+        let markdown_str = "Some text{-a *margin* note} and a sidenote{numbered}.";
+        let parsed = html_from_markdown(markdown_str, "".to_string()).expect("Should succeed");
+        assert_eq!(parsed.html, r#"<article>
+<p>Some text<label for="mn-1" class="margin-toggle">&#8853;</label><input type="checkbox" id="mn-1" class="margin-toggle"/><span class="marginnote">a <em>margin</em> note</span> and a sidenote<label for="sn-1" id="sn-1-ref" class="margin-toggle sidenote-number"></label><input type="checkbox" id="sn-1" class="margin-toggle"/><span class="sidenote">numbered</span>.</p>
+</section></article>"#);
+        assert_eq!(parsed.sidenotes, vec!["numbered"]);
+        assert_eq!(parsed.excerpt, "Some text and a sidenote.");
+        let feed = feed_html_from_markdown(markdown_str, "".to_string()).expect("Should succeed");
+        assert!(feed.html.contains(r#"<span class="marginnote">a <em>margin</em> note</span>"#));
+        assert!(feed.html.contains(r#"<ol class="footnotes"><li>numbered</li></ol>"#));
+        assert!(html_from_markdown("{-nested {sidenote}}", "".to_string()).is_err());
+    }
+
+    #[test]
+    fn can_render_markdown_footnotes() {
+        let braces = "Some text{a *first* note} and more{second [link](x/)}.\n\nAfter.";
+        let footnotes = r#"Some text[^1] and more[^second].
+
+[^1]: a *first* note
+
+[^second]: second [link](x/)
+
+After."#;
+        let expected = html_from_markdown(braces, "/p/".to_string()).expect("Should succeed");
+        let parsed = html_from_markdown(footnotes, "/p/".to_string()).expect("Should succeed");
+        assert_eq!(parsed.html, expected.html);
+        assert_eq!(parsed.sidenotes, vec!["a <em>first</em> note", "second <a href=\"/p/x/\">link</a>"]);
+        let feed = feed_html_from_markdown(footnotes, "/p/".to_string()).expect("Should succeed");
+        assert_eq!(feed.html, feed_html_from_markdown(braces, "/p/".to_string()).unwrap().html);
+        assert!(html_from_markdown("A missing[^x] note.", "".to_string()).unwrap().html
+                .contains("A missing[^x] note."));
+        assert!(html_from_markdown("A {nested[^1]}\n\n[^1]: note", "".to_string()).is_err());
+    }
+
+    #[test]
+    fn can_link_headings() {
+        let md = r#"
+Title
+=====
+
+## A *first* section
+
+### Fish & chips
+
+## A first section
+
+Text.
+"#;
+        let parsed = html_from_markdown(md, "".to_string()).expect("Should succeed");
+        assert_eq!(parsed.html, r##"<article>
+<h1>Title</h1><section><h2 id="a-first-section">A <em>first</em> section<a class="anchor" href="#a-first-section">#</a></h2>
+<h3 id="fish-chips">Fish &amp; chips<a class="anchor" href="#fish-chips">#</a></h3>
+<h2 id="a-first-section-1">A first section<a class="anchor" href="#a-first-section-1">#</a></h2>
+<p>Text.</p>
+</section></article>"##);
+        let ids: Vec<&str> = parsed.headings.iter().map(|h| h.id.as_str()).collect();
+        assert_eq!(ids, vec!["a-first-section", "fish-chips", "a-first-section-1"]);
+        assert_eq!(parsed.headings[1].title, "Fish &amp; chips");
+
+        let mut post = IndexedBlogPost::example();
+        let data = PostData::from(("", &mut post, "/", "/post/".to_string(), vec![],
+                                   parsed.headings));
+        let json = serde_json::to_value(&data).unwrap();
+        assert_eq!(json["toc"][0]["children"][0]["id"], "fish-chips");
+        assert_eq!(json["toc"][1]["title"], "A first section");
+
+        let md = "## Results{see appendix}\n\n## $x^2$ math \\{in `{braces}`\\}\n";
+        let parsed = html_from_markdown(md, "".to_string()).expect("Should succeed");
+        let titles: Vec<(&str, &str)> = parsed.headings.iter()
+            .map(|h| (h.id.as_str(), h.title.as_str()))
+            .collect();
+        assert_eq!(titles, vec![("results", "Results"), ("math-in-braces", "math {in {braces}}")]);
+        assert!(parsed.html.contains("<h2 id=\"results\">Results<label"));
+    }
+
+    #[test]
+    fn can_substitute_variables() {
+        let md = r#"---
+mood: cheerful
+---
+A {{meta.mood}} [demo]({{post_url}}demo/){see {{ index_url }}} and `{{code}}`
+
+<iframe src="{{post_url}}demo.html"></iframe>
+"#;
+        let mut variables = Variables::new();
+        variables.insert("post_url".to_string(), "/blog/post/".to_string());
+        variables.insert("index_url".to_string(), "/blog/".to_string());
+        let parsed = html_from_markdown_with_variables(md, "/blog/post/".to_string(), &variables)
+            .expect("Should succeed");
+        assert!(parsed.html.starts_with(r#"<article>
+<p>A cheerful <a href="/blog/post/demo/">demo</a><label"#));
+        assert!(parsed.html.contains(r#"and <code>{{code}}</code></p>
+<iframe src="/blog/post/demo.html"></iframe>"#));
+        assert_eq!(parsed.sidenotes, vec!["see /blog/"]);
+
+        match html_from_markdown("Text\n\nwith {{missing}}", "".to_string()) {
+            Err(SidenoteError::Variable(name, p)) => {
+                assert_eq!(name, "missing");
+                assert_eq!((p.line, p.column), (3, 6));
+            },
+            other => panic!("Expected a variable error, got {:?}", other.map(|p| p.html))
+        }
+        assert!(html_from_markdown("<a href=\"{{missing}}\">", "".to_string()).is_err());
+
+        let md = "Write \\{{post_url}} for {{post_url}}, or \\{ a brace.\n\n<script type=\"text/x-handlebars\">\n\\{{name}}\n</script>\n";
+        let parsed = html_from_markdown_with_variables(md, "".to_string(), &variables)
+            .expect("Should succeed");
+        assert!(parsed.html.contains("<p>Write {{post_url}} for /blog/post/, or { a brace.</p>"));
+        assert!(parsed.html.contains("<script type=\"text/x-handlebars\">\n{{name}}\n</script>"));
+    }
+
+    #[test]
+    fn can_list_sidenote_ids() {
+        let mut post = IndexedBlogPost::example();
+        let data = PostData::from(("", &mut post, "/", "/post/".to_string(),
+                                   vec!["first".to_string(), "second".to_string()], vec![]));
+        let json = serde_json::to_value(&data).unwrap();
+        assert_eq!(json["sidenotes"][1]["id"], "sn-2");
+        assert_eq!(json["sidenotes"][1]["note"], "second");
+    }
+
     #[test]
     fn check_to_markdown() {
         let markdown_str = r#"
@@ -386,13 +877,13 @@ code_with{
 
 "#;
         let html_buf = html_from_markdown(markdown_str, "".to_string()).expect("Shouldn't fail!");
-        assert_eq!(html_buf.sidenotes, vec!["sidenotes ", "sidenotes "]);
+        assert_eq!(html_buf.sidenotes, vec!["sidenotes", "sidenotes"]);
 
         assert_eq!(
             html_buf.html,
             r#"<article>
 <h1>hello</h1><section>
-<p>Here is some text with <label class="sidenote-number"></label><span class="sidenote">sidenotes</span> and <label class="sidenote-number"></label><span class="sidenote">sidenotes</span>.</p>
+<p>Here is some text with <label for="sn-1" id="sn-1-ref" class="margin-toggle sidenote-number"></label><input type="checkbox" id="sn-1" class="margin-toggle"/><span class="sidenote">sidenotes</span> and <label for="sn-2" id="sn-2-ref" class="margin-toggle sidenote-number"></label><input type="checkbox" id="sn-2" class="margin-toggle"/><span class="sidenote">sidenotes</span>.</p>
 <ul>
 <li>alpha</li>
 <li>beta</li>
@@ -416,8 +907,10 @@ Here is some text with {sidenotes}.
 "#;
         let mut title: Option<String> = None;
         let mut sidenotes: Vec<String> = vec![];
+        let mut headings = vec![];
         {
-            let parser = SidenoteParser::new(Parser::new(md), &mut title, &mut sidenotes);
+            let parser = SidenoteParser::new(Parser::new(md), &mut title, &mut sidenotes,
+                                             &mut headings);
             for _ in parser {}
         }
         assert_eq!(title.expect("Should work, even with ampersands!"), "hello & hello")
@@ -430,13 +923,96 @@ hello
 =====
 
 ![image](https://image)
+
+An inline ![image](https://image) stays in the text.
+
+![a *wide* image](wide.png "title"){.fullwidth}
+
+![in the margin](margin.png) {.margin}
 "#;
         assert_eq!(html_from_markdown(md, "".to_string()).expect("should work!").html, r#"<article>
+<h1>hello</h1><section><figure><img src="https://image" alt="image" /><figcaption>image</figcaption></figure>
+<p>An inline <img src="https://image" alt="" /><br /><span class="image-caption">image</span> stays in the text.</p>
+<figure class="fullwidth"><img src="wide.png" alt="a wide image" title="title" /><figcaption>a <em>wide</em> image</figcaption></figure>
+<p><label for="mn-1" class="margin-toggle">&#8853;</label><input type="checkbox" id="mn-1" class="margin-toggle"/><span class="marginnote"><img src="margin.png" alt="in the margin" />in the margin</span></p>
+</section></article>"#);
+        let feed = feed_html_from_markdown("![in the margin](margin.png){.margin}", "".to_string())
+            .expect("should work!");
+        assert!(feed.html.contains("<figure><img src=\"margin.png\" alt=\"in the margin\" />"));
+    }
+
+    #[test]
+    fn can_parse_figure_captions() {
+        let md = "---\nmood: cheerful\n---\n![see $x^2$, \\{braces\\} and a {{meta.mood}} *face*](a.png)\n";
+        let html = html_from_markdown(md, "".to_string()).expect("should work!").html;
+        assert!(html.contains("<figure><img src=\"a.png\" alt=\"see , {braces} and a cheerful face\" />"));
+        assert!(html.contains(concat!(
+            "<figcaption>see <math xmlns=\"http://www.w3.org/1998/Math/MathML\"><msup><mi>x</mi>",
+            "<mn>2</mn></msup></math>, {braces} and a cheerful <em>face</em></figcaption>")));
+
+        match html_from_markdown("Text.\n\n![see {a note}](a.png)", "".to_string()) {
+            Err(SidenoteError::InCaption(p)) => assert_eq!((p.line, p.column), (3, 7)),
+            other => panic!("Expected a caption error, got {:?}", other.map(|p| p.html))
+        }
+        match html_from_markdown("![{{missing}}](a.png)", "".to_string()) {
+            Err(SidenoteError::Variable(name, _)) => assert_eq!(name, "missing"),
+            other => panic!("Expected a variable error, got {:?}", other.map(|p| p.html))
+        }
+    }
+
+    #[test]
+    fn can_strip_front_matter() {
+        let md = r#"---
+title: Front matter title
+description: hello
+---
+hello
+=====
+
+Here is some text.
+"#;
+        let parsed = html_from_markdown(md, "".to_string()).expect("should work!");
+        assert_eq!(parsed.title, Some("Front matter title".to_string()));
+        assert_eq!(parsed.front_matter.description, Some("hello".to_string()));
+        assert_eq!(parsed.html, r#"<article>
 <h1>hello</h1><section>
-<p><img src="https://image" alt="" /><br /><span class="image-caption">image</span></p>
+<p>Here is some text.</p>
 </section></article>"#);
     }
 
+    #[test]
+    fn can_render_footnotes_for_feeds() {
+        let md = r#"
+hello
+=====
+
+Here is some *text* with {a *formatted* sidenote} and {another
+sidenote}.
+
+Second paragraph.
+"#;
+        let parsed = feed_html_from_markdown(md, "".to_string()).expect("should work!");
+        assert_eq!(parsed.html, r#"<article>
+<h1>hello</h1><section>
+<p>Here is some <em>text</em> with <sup class="footnote-ref">1</sup> and <sup class="footnote-ref">2</sup>.</p>
+<p>Second paragraph.</p>
+<ol class="footnotes"><li>a <em>formatted</em> sidenote</li><li>another
+sidenote</li></ol></section></article>"#);
+        assert_eq!(parsed.excerpt, "Here is some text with and. Second paragraph.");
+        let parsed = html_from_markdown("Use `foo` now{a note}, not {-later}.", "".to_string())
+            .expect("should work!");
+        assert_eq!(parsed.excerpt, "Use foo now, not.");
+    }
+
+    #[test]
+    fn can_truncate_excerpt() {
+        let long = "word ".repeat(100);
+        let excerpt = truncate_excerpt(&long);
+        assert!(excerpt.ends_with("word…"));
+        assert!(excerpt.chars().count() <= 301);
+        assert_eq!(truncate_excerpt(" a  short\nexcerpt "), "a short excerpt");
+    }
+
     #[test]
     fn check_absolute_links() {
         assert!(SidenoteParser::link_is_relative(&Cow::from("link.jpg")));
@@ -461,8 +1037,8 @@ hello
         assert_eq!(html_from_markdown(md, "/prefix/".to_string()).expect("should work!").html, r#"<article>
 <h1>hello</h1><section>
 <p><a href="/prefix/relative-link">link</a></p>
-<p><img src="https://image" alt="" /><br /><span class="image-caption">image</span></p>
-<p><img src="/prefix/relative-image.jpg" alt="" /><br /><span class="image-caption">image</span></p>
+<figure><img src="https://image" alt="image" /><figcaption>image</figcaption></figure>
+<figure><img src="/prefix/relative-image.jpg" alt="image" /><figcaption>image</figcaption></figure>
 </section></article>"#);
     }
 }
@@ -5,20 +5,30 @@ use std::time::SystemTime;
 use url::Url;
 use serde_json;
 use url_serde;
+use chrono::{DateTime, SecondsFormat, Utc};
 
 use toc::IndexedBlogPost;
+use parser::ParsedMarkdown;
+use highlight::{theme_names, DEFAULT_THEME};
 
 
 const CORE_DATA_PATH: &str = ".meta.json";
 
+/// the number of posts included in the feeds
+pub const FEED_LENGTH: usize = 10;
+
 
 #[derive(Serialize)]
 struct RssPost {
     title: Option<String>,
     first_published: SystemTime,
+    last_updated: SystemTime,
     author: String,
     #[serde(with = "url_serde")]
-    link: Url
+    link: Url,
+    description: Option<String>,
+    content: String,
+    categories: Vec<String>
 }
 
 
@@ -27,16 +37,23 @@ impl RssPost {
         RssPost{
             title: None,
             first_published: SystemTime::now(),
+            last_updated: SystemTime::now(),
             author: "Me".to_string(),
-            link: Url::parse("https://example.com").unwrap()
+            link: Url::parse("https://example.com").unwrap(),
+            description: Some("An example".to_string()),
+            content: "<p>An example</p>".to_string(),
+            categories: vec!["example".to_string()]
         }
     }
 }
 
 
+/// Data for rendering the RSS and Atom feeds. `updated` is the
+/// time of the most recent update to any of the posts.
 #[derive(Serialize)]
 pub struct RssData {
     core_data: CoreData,
+    updated: SystemTime,
     posts: Vec<RssPost>
 }
 
@@ -45,28 +62,115 @@ impl RssData {
     pub fn example() -> Self {
         RssData{
             core_data: CoreData::new("bla", "https://bla.com", "2", "3", "5").unwrap(),
+            updated: SystemTime::now(),
             posts: vec![RssPost::example()]
         }
     }
 
     pub fn new(core_data: CoreData) -> Self {
-        RssData{core_data, posts: vec![]}
-    }
-
-    pub fn push_posts(&mut self, posts: &[IndexedBlogPost]) {
-        for (i, post) in posts.iter().rev().enumerate() {
-            let mut link = self.core_data.home.clone();
-            link.set_path(&post.post_url);
-            self.posts.push(RssPost{
-                link, author: self.core_data.author.clone(),
-                first_published: post.first_published,
-                title: post.title.clone()
-            });
-            if i == 9 {
-                break;
-            }
+        RssData{core_data, updated: SystemTime::now(), posts: vec![]}
+    }
+
+    /// add a post to the feed, with the article as rendered for feeds
+    pub fn push_post(&mut self, post: &IndexedBlogPost, article: &ParsedMarkdown) {
+        if self.posts.is_empty() || post.last_updated > self.updated {
+            self.updated = post.last_updated;
+        }
+        let mut link = self.core_data.home.clone();
+        link.set_path(&post.post_url);
+        self.posts.push(RssPost{
+            link, author: self.core_data.author.clone(),
+            first_published: post.first_published,
+            last_updated: post.last_updated,
+            title: post.title.clone(),
+            description: summary(post, article),
+            content: article.html.clone(),
+            categories: post.meta.tags.clone()
+        });
+    }
+}
+
+
+// the description from the front matter, falling back to the
+// beginning of the article
+fn summary(post: &IndexedBlogPost, article: &ParsedMarkdown) -> Option<String> {
+    match post.meta.description {
+        Some(ref d) if !d.trim().is_empty() => Some(d.clone()),
+        _ if !article.excerpt.is_empty() => Some(article.excerpt.clone()),
+        _ => None
+    }
+}
+
+
+fn rfc3339(t: SystemTime) -> String {
+    DateTime::<Utc>::from(t).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+
+#[derive(Serialize)]
+struct JsonFeedAuthor {
+    name: String
+}
+
+
+#[derive(Serialize)]
+struct JsonFeedItem {
+    id: String,
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    content_html: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    date_published: String,
+    date_modified: String,
+    tags: Vec<String>
+}
+
+
+/// A feed following the JSON Feed 1.1 spec, https://jsonfeed.org/version/1.1.
+/// Unlike the RSS and Atom feeds, it is serialized directly rather than
+/// rendered through a template.
+#[derive(Serialize)]
+pub struct JsonFeed {
+    version: &'static str,
+    title: String,
+    home_page_url: String,
+    feed_url: String,
+    description: String,
+    authors: Vec<JsonFeedAuthor>,
+    items: Vec<JsonFeedItem>
+}
+
+
+impl JsonFeed {
+    pub fn new(core_data: &CoreData) -> Self {
+        JsonFeed{
+            version: "https://jsonfeed.org/version/1.1",
+            title: core_data.title.clone(),
+            home_page_url: core_data.index_url.to_string(),
+            feed_url: format!("{}feed.json", core_data.index_url),
+            description: core_data.description.clone(),
+            authors: vec![JsonFeedAuthor{name: core_data.author.clone()}],
+            items: vec![]
         }
     }
+
+    pub fn push_post(&mut self, core_data: &CoreData, post: &IndexedBlogPost,
+                     article: &ParsedMarkdown) {
+        let mut link = core_data.home.clone();
+        link.set_path(&post.post_url);
+        self.items.push(JsonFeedItem{
+            id: link.to_string(),
+            url: link.to_string(),
+            title: post.title.clone(),
+            content_html: article.html.clone(),
+            summary: summary(post, article),
+            date_published: rfc3339(post.first_published),
+            date_modified: rfc3339(post.last_updated),
+            tags: post.meta.tags.clone()
+        });
+    }
 }
 
 
@@ -78,7 +182,11 @@ pub struct CoreData {
     description: String,
     author: String,
     #[serde(with = "url_serde")]
-    index_url: Url
+    index_url: Url,
+    #[serde(default)]
+    posts_per_page: Option<usize>,
+    #[serde(default)]
+    highlight_theme: Option<String>
 }
 
 
@@ -129,7 +237,9 @@ impl CoreData {
                     title: title.to_string(),
                     description: description.to_string(),
                     author: author.to_string(),
-                    home, index_url
+                    home, index_url,
+                    posts_per_page: None,
+                    highlight_theme: None
                 })
             } else {
                 Err(RSSError{
@@ -145,6 +255,46 @@ impl CoreData {
         }
     }
 
+    /// paginate the table of contents with this many posts per page
+    pub fn set_posts_per_page(&mut self, posts_per_page: Option<usize>) {
+        self.posts_per_page = posts_per_page;
+    }
+
+    pub fn posts_per_page(&self) -> Option<usize> {
+        self.posts_per_page
+    }
+
+    /// colour highlighted code with one of the bundled themes
+    pub fn set_highlight_theme(&mut self, theme: Option<String>) -> Result<(), RSSError> {
+        if let Some(ref t) = theme {
+            let themes = theme_names();
+            if !themes.contains(t) {
+                return Err(RSSError{
+                    msg: format!("Unknown highlighting theme {}. Choose one of: {}",
+                                 t, themes.join(", ")),
+                    kind: ErrorKind::BadSyntax
+                });
+            }
+        }
+        self.highlight_theme = theme;
+        Ok(())
+    }
+
+    pub fn highlight_theme(&self) -> &str {
+        match self.highlight_theme {
+            Some(ref t) => t,
+            None => DEFAULT_THEME
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn home(&self) -> &Url {
+        &self.home
+    }
+
     pub fn load() -> Result<Self, RSSError> {
         let data_json = match fs::read_to_string(CORE_DATA_PATH) {
             Ok(j) => j,
@@ -184,7 +334,11 @@ impl CoreData {
 
 #[cfg(test)]
 mod test {
-    use super::CoreData;
+    use std::time::{Duration, UNIX_EPOCH};
+    use serde_json;
+    use super::{CoreData, JsonFeed, RssData};
+    use parser::feed_html_from_markdown;
+    use toc::IndexedBlogPost;
     use url::Url;
 
     #[test]
@@ -195,4 +349,55 @@ mod test {
                    .expect("Can't create new coredata").home,
                    Url::parse("https://example.com/").unwrap());
     }
+
+    #[test]
+    fn can_set_highlight_theme() {
+        let mut core_data = CoreData::new("a", "https://example.com/", "c", "d", "e")
+            .expect("Can't create new coredata");
+        assert_eq!(core_data.highlight_theme(), "InspiredGitHub");
+        assert!(core_data.set_highlight_theme(Some("not-a-theme".to_string())).is_err());
+        core_data.set_highlight_theme(Some("Solarized (dark)".to_string()))
+            .expect("Can't set theme");
+        assert_eq!(core_data.highlight_theme(), "Solarized (dark)");
+    }
+
+    #[test]
+    fn can_serialize_json_feed() {
+        let core_data = CoreData::new("a", "https://example.com/", "c", "d", "/blog/")
+            .expect("Can't create new coredata");
+        let mut post = IndexedBlogPost::example();
+        post.post_url = "/blog/post-1/".to_string();
+        post.title = Some("A post".to_string());
+        post.first_published = UNIX_EPOCH;
+        post.last_updated = UNIX_EPOCH + Duration::from_secs(60);
+        let article = feed_html_from_markdown("", "/".to_string()).unwrap();
+        let mut feed = JsonFeed::new(&core_data);
+        feed.push_post(&core_data, &post, &article);
+        let json = serde_json::to_value(&feed).unwrap();
+        assert_eq!(json["version"], "https://jsonfeed.org/version/1.1");
+        assert_eq!(json["feed_url"], "https://example.com/blog/feed.json");
+        assert_eq!(json["authors"][0]["name"], "d");
+        let item = &json["items"][0];
+        assert_eq!(item["id"], "https://example.com/blog/post-1/");
+        assert_eq!(item["title"], "A post");
+        assert_eq!(item["content_html"], article.html.as_str());
+        assert_eq!(item["date_published"], "1970-01-01T00:00:00Z");
+        assert_eq!(item["date_modified"], "1970-01-01T00:01:00Z");
+        assert!(item.get("summary").is_none());
+    }
+
+    #[test]
+    fn can_summarize_posts() {
+        let core_data = CoreData::new("a", "https://example.com/", "c", "d", "/blog/")
+            .expect("Can't create new coredata");
+        let mut post = IndexedBlogPost::example();
+        let article = feed_html_from_markdown("Some *text*{a note}.", "/".to_string()).unwrap();
+        let mut rss_data = RssData::new(core_data);
+        rss_data.push_post(&post, &article);
+        post.meta.description = Some("A description".to_string());
+        rss_data.push_post(&post, &article);
+        assert_eq!(rss_data.posts[0].description, Some("Some text.".to_string()));
+        assert_eq!(rss_data.posts[1].description, Some("A description".to_string()));
+        assert!(rss_data.posts[0].content.contains("<li>a note</li>"));
+    }
 }
@@ -0,0 +1,298 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use watch::{Watcher, POLL_INTERVAL};
+
+
+/// the path the browsers listen on for reloads
+const RELOAD_PATH: &str = "/__wellington/reload";
+
+/// reload the page whenever the server sends an event
+const RELOAD_SCRIPT: &str = concat!(
+    "<script>new EventSource(\"/__wellington/reload\")",
+    ".onmessage = function() { location.reload(); };</script>");
+
+/// how long to wait before pinging browsers, to notice when they're gone
+const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+
+/// counts the syncs, for the browsers to reload after each one
+struct Reloads {
+    generation: Mutex<u64>,
+    changed: Condvar
+}
+
+
+impl Reloads {
+    fn new() -> Self {
+        Reloads{generation: Mutex::new(0), changed: Condvar::new()}
+    }
+
+    fn current(&self) -> u64 {
+        *self.generation.lock().unwrap()
+    }
+
+    fn notify(&self) {
+        *self.generation.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+
+    /// wait for the next reload after `generation`, or until it's time for
+    /// a ping
+    fn wait(&self, generation: u64) -> u64 {
+        let guard = self.generation.lock().unwrap();
+        if *guard != generation {
+            return *guard;
+        }
+        let (guard, _) = self.changed.wait_timeout(guard, PING_INTERVAL).unwrap();
+        *guard
+    }
+}
+
+
+/// what to answer to a request
+#[derive(Debug, PartialEq)]
+enum Route {
+    File(PathBuf),
+    Redirect(String),
+    Reloads,
+    NotFound
+}
+
+
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = path.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+
+/// find the file for the `path` of a request. The server is rooted one
+/// level above the blog, so that the URLs are the same as once published,
+/// like `/blog/post-1/`.
+fn route(root: &Path, path: &str) -> Route {
+    let path = path.split('?').next().unwrap_or("");
+    if path == RELOAD_PATH {
+        return Route::Reloads;
+    }
+    let decoded = match percent_decode(path) {
+        Some(p) => p,
+        None => { return Route::NotFound; }
+    };
+    let mut file = root.to_path_buf();
+    for part in decoded.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => { return Route::NotFound; },
+            p => file.push(p)
+        }
+    }
+    if file.is_dir() {
+        // relative links in the posts need the trailing slash
+        if !path.ends_with('/') {
+            return Route::Redirect(format!("{}/", path));
+        }
+        file.push("index.html");
+    }
+    if file.is_file() {
+        Route::File(file)
+    } else {
+        Route::NotFound
+    }
+}
+
+
+fn content_type(file: &Path) -> &'static str {
+    let extension = match file.extension().and_then(|e| e.to_str()) {
+        Some(e) => e.to_lowercase(),
+        None => "".to_string()
+    };
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "application/javascript; charset=utf-8",
+        "json" => "application/json; charset=utf-8",
+        "xml" => "application/xml; charset=utf-8",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream"
+    }
+}
+
+
+/// add the script listening for reloads to a page
+fn inject_reload_script(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(i) => format!("{}{}{}", &html[..i], RELOAD_SCRIPT, &html[i..]),
+        None => format!("{}{}", html, RELOAD_SCRIPT)
+    }
+}
+
+
+fn respond(stream: &mut TcpStream, status: &str, headers: &str, body: &[u8]) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+           status, headers, body.len())?;
+    stream.write_all(body)
+}
+
+
+// keep the connection open, sending an event on every reload
+fn send_reloads(stream: &mut TcpStream, reloads: &Reloads) -> io::Result<()> {
+    stream.write_all(concat!("HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n",
+                             "Cache-Control: no-cache\r\n\r\n").as_bytes())?;
+    let mut generation = reloads.current();
+    loop {
+        let next = reloads.wait(generation);
+        if next != generation {
+            generation = next;
+            stream.write_all(b"data: reload\n\n")?;
+        } else {
+            stream.write_all(b": ping\n\n")?;
+        }
+        stream.flush()?;
+    }
+}
+
+
+fn handle(mut stream: TcpStream, root: &Path, reloads: &Reloads) -> io::Result<()> {
+    let mut request_line = String::new();
+    {
+        let mut reader = BufReader::new(&stream);
+        reader.read_line(&mut request_line)?;
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+    }
+    let parts: Vec<&str> = request_line.split_whitespace().collect();
+    if parts.len() < 2 || parts[0] != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "", b"Method not allowed");
+    }
+    match route(root, parts[1]) {
+        Route::Reloads => send_reloads(&mut stream, reloads),
+        Route::Redirect(location) => respond(&mut stream, "301 Moved Permanently",
+                                             &format!("Location: {}\r\n", location), b""),
+        Route::NotFound => respond(&mut stream, "404 Not Found",
+                                   "Content-Type: text/plain\r\n", b"Not found"),
+        Route::File(file) => {
+            let content_type = content_type(&file);
+            let mut body = fs::read(&file)?;
+            if content_type.starts_with("text/html") {
+                body = inject_reload_script(&String::from_utf8_lossy(&body)).into_bytes();
+            }
+            respond(&mut stream, "200 OK", &format!("Content-Type: {}\r\n", content_type), &body)
+        }
+    }
+}
+
+
+/// Serve the blog at `blog_dir` on localhost, for previewing. When a post
+/// or template changes, it is synced like `watch` does and the open pages
+/// reload.
+pub fn serve(blog_dir: PathBuf, port: u16) -> io::Result<()> {
+    let root = match blog_dir.parent() {
+        Some(p) => p.to_path_buf(),
+        None => {
+            return Err(io::Error::other("the blog can't be at the root of the file system"));
+        }
+    };
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let reloads = Arc::new(Reloads::new());
+    let blog_name = blog_dir.file_name().map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    println!("Serving the blog at http://127.0.0.1:{}/{}/", port, blog_name);
+
+    let mut watcher = Watcher::new(blog_dir);
+    let server_reloads = reloads.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(|s| s.ok()) {
+            let root = root.clone();
+            let reloads = server_reloads.clone();
+            thread::spawn(move || {
+                // the browser closed the connection
+                let _ = handle(stream, &root, &reloads);
+            });
+        }
+    });
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        if let Some(true) = watcher.sync_changes() {
+            reloads.notify();
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use super::{route, content_type, inject_reload_script, percent_decode, Route, RELOAD_SCRIPT};
+
+    fn create_site() -> PathBuf {
+        let mut root = env::temp_dir();
+        root.push("wellington-serve");
+        let _ = fs::remove_dir_all(&root);
+        for dir in &["blog/post-1", "blog/tags/rust"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in &["blog/index.html", "blog/.post_template.html", "blog/.index.csv",
+                      "blog/rss.xml", "blog/post-1/index.md", "blog/post-1/index.html",
+                      "blog/post-1/a b.png", "blog/tags/rust/index.html"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn can_route() {
+        let root = create_site();
+        assert_eq!(route(&root, "/blog/post-1/"),
+                   Route::File(root.join("blog/post-1/index.html")));
+        assert_eq!(route(&root, "/blog/post-1"), Route::Redirect("/blog/post-1/".to_string()));
+        assert_eq!(route(&root, "/blog/post-1/a%20b.png?v=1"),
+                   Route::File(root.join("blog/post-1/a b.png")));
+        assert_eq!(route(&root, "/blog/missing/"), Route::NotFound);
+        assert_eq!(route(&root, "/blog/../../etc/passwd"), Route::NotFound);
+        assert_eq!(route(&root, "/__wellington/reload"), Route::Reloads);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn can_prepare_responses() {
+        assert_eq!(content_type(Path::new("a/index.html")), "text/html; charset=utf-8");
+        assert_eq!(content_type(Path::new("a/IMAGE.JPG")), "image/jpeg");
+        assert_eq!(content_type(Path::new("a/README")), "application/octet-stream");
+        assert_eq!(inject_reload_script("<body>hi</body>"),
+                   format!("<body>hi{}</body>", RELOAD_SCRIPT));
+        assert_eq!(percent_decode("%C3%BC%20x"), Some("ü x".to_string()));
+        assert_eq!(percent_decode("%zz"), None);
+    }
+}
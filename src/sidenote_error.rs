@@ -1,31 +1,112 @@
 use std::fmt;
 
 
+/// where in the markdown an error was found. Lines and columns count
+/// from 1, and `snippet` is the offending line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    pub snippet: String
+}
+
+
+impl Position {
+    /// the position of the byte `offset` in `source`
+    pub fn new(source: &str, offset: usize) -> Position {
+        let mut offset = offset.min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line_start = source[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = source[offset..].find('\n').map(|i| offset + i).unwrap_or_else(|| source.len());
+        Position{
+            line: source[..offset].matches('\n').count() + 1,
+            column: source[line_start..offset].chars().count() + 1,
+            snippet: source[line_start..line_end].trim_end().to_string()
+        }
+    }
+}
+
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}:\n    {}\n    {}^", self.line, self.column,
+               self.snippet, " ".repeat(self.column - 1))
+    }
+}
+
+
 /// sidenote errors. The possible errors are:
 /// 
 /// * not matched, e.g. "bla { bla" or "bla } {bla}"
 /// * nested, e.g. "{ bla { }"
+///
+/// Both say where the offending brace is, as do unknown variables like
+/// `{{unknown}}`. Errors rendering the template, reading the front matter
+/// or rendering math are reported here too.
 #[derive(Debug)]
 pub enum SidenoteError{
-    NotMatched,
-    Nested,
-    Template(String)
+    NotMatched(Position),
+    Nested(Position),
+    Variable(String, Position),
+    InCaption(Position),
+    Template(String),
+    FrontMatter(String),
+    Math(String)
 }
 
 
 impl fmt::Display for SidenoteError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            SidenoteError::NotMatched => {
-                write!(f, "Error: a sidenote delimiter was not matched")
+            SidenoteError::NotMatched(p) => {
+                write!(f, "Error: a sidenote delimiter was not matched at {}", p)
+            },
+            SidenoteError::Nested(p) => {
+                write!(f, "Error: encountered a nested sidenote at {}", p)
             },
-            SidenoteError::Nested => {
-                write!(f, "Error: encountered a nested sidenote")
+            SidenoteError::Variable(name, p) => {
+                write!(f, "Error: unknown variable '{}' at {}", name, p)
+            },
+            SidenoteError::InCaption(p) => {
+                write!(f, "Error: a figure caption can't have a sidenote, at {}", p)
             },
             SidenoteError::Template(s) => {
                 write!(f, "Couldn't render template: {}", s)
+            },
+            SidenoteError::FrontMatter(s) => {
+                write!(f, "Error: bad front matter: {}", s)
+            },
+            SidenoteError::Math(s) => {
+                write!(f, "Error: can't render math: {}", s)
             }
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::{Position, SidenoteError};
+
+    #[test]
+    fn can_find_position() {
+        let source = "first line\nsecond {line}\n\nthird ü }";
+        assert_eq!(Position::new(source, 0),
+            Position{line: 1, column: 1, snippet: "first line".to_string()});
+        assert_eq!(Position::new(source, 18),
+            Position{line: 2, column: 8, snippet: "second {line}".to_string()});
+        assert_eq!(Position::new(source, source.len() - 1),
+            Position{line: 4, column: 9, snippet: "third ü }".to_string()});
+        assert_eq!(Position::new("", 5),
+            Position{line: 1, column: 1, snippet: "".to_string()});
+    }
+
+    #[test]
+    fn can_show_position() {
+        let error = SidenoteError::Nested(Position::new("a {b {c}}", 5));
+        assert_eq!(format!("{}", error),
+            "Error: encountered a nested sidenote at line 1, column 6:\n    a {b {c}}\n         ^");
+    }
+}
@@ -1,13 +1,60 @@
 use itertools::Itertools;
-use pulldown_cmark::Event;
+use pulldown_cmark::{Event, Tag};
 use regex::Regex;
 use std::borrow::Cow;
 
 use parser::SidenoteParser;
 use sidenote_error::SidenoteError;
+use math::latex_to_mathml;
 
 
-static START_SIDENOTE: &str = r#"<label class="sidenote-number"></label><span class="sidenote">"#;
+static START_MARGIN_NOTE: &str = r#"<span class="marginnote">"#;
+
+
+/// the id of the `n`th sidenote, counting from 1
+pub fn sidenote_id(n: usize) -> String {
+    format!("sn-{}", n)
+}
+
+
+/// the Tufte CSS markup opening the `n`th sidenote: a label showing the
+/// number, and a checkbox for the label to toggle the note on narrow screens
+fn start_sidenote(n: usize) -> String {
+    format!(concat!(r#"<label for="{0}" id="{0}-ref" class="margin-toggle sidenote-number"></label>"#,
+                    r#"<input type="checkbox" id="{0}" class="margin-toggle"/><span class="sidenote">"#),
+            sidenote_id(n))
+}
+
+
+/// like `start_sidenote`, but margin notes are toggled with a ⊕ instead
+/// of a number
+pub fn start_margin_note(n: usize) -> String {
+    format!(concat!(r#"<label for="mn-{0}" class="margin-toggle">&#8853;</label>"#,
+                    r#"<input type="checkbox" id="mn-{0}" class="margin-toggle"/>{1}"#),
+            n, START_MARGIN_NOTE)
+}
+
+
+/// the position of the `$` or `$$` closing a math span. Like pandoc, an
+/// inline closing `$` can't follow a space or precede a digit, so that
+/// "from $5 to $10" is not math.
+fn find_closing_math(tex: &str, display: bool) -> Option<usize> {
+    let bytes = tex.as_bytes();
+    for (i, _) in tex.match_indices('$') {
+        if i > 0 && bytes[i - 1] == b'\\' {
+            continue;
+        }
+        if display {
+            if bytes.get(i + 1) == Some(&b'$') {
+                return Some(i);
+            }
+        } else if i > 0 && !(bytes[i - 1] as char).is_whitespace()
+            && !bytes.get(i + 1).is_some_and(|b| b.is_ascii_digit()) {
+            return Some(i);
+        }
+    }
+    None
+}
 
 
 /// compile sidenotes
@@ -16,25 +63,17 @@ static START_SIDENOTE: &str = r#"<label class="sidenote-number"></label><span cl
 impl<'a> SidenoteParser<'a> { 
 
     fn parse_first_sidenote<'b>(&'b mut self, text: Cow<'a, str>) -> Event<'a> {
-        let re = Regex::new(r"[{}]").unwrap();
+        let re = Regex::new(r"[{}$]").unwrap();
 
         match re.find(&text) {
             Some(m) => {
                 assert_eq!(m.start() + 1, m.end());
                 let first = text[..m.start()].to_string();
                 self.remaining_text = text[m.start()..].to_string();
-                if self.in_sidenote_block {
-                    self.sidenotes.last_mut().unwrap().push_str(&first);
-                    self.sidenotes.last_mut().unwrap().push_str(" ");
-                }
                 Event::Text(Cow::from(first))
             },
             None => {
                 self.remaining_text = "".to_string();
-                if self.in_sidenote_block {
-                    self.sidenotes.last_mut().unwrap().push_str(&text);
-                    self.sidenotes.last_mut().unwrap().push_str(" ");
-                }
                 Event::Text(Cow::from(text.to_string()))
                 // can I avoid this pointless copy?
                 // how do I tell the compiler that if I return, then
@@ -56,25 +95,126 @@ impl<'a> SidenoteParser<'a> {
         first_char
     }
 
+    /// render math starting at a `$`, which has just been taken off
+    /// `remaining_text`. A math span can continue over several events, so
+    /// read ahead until the closing delimiter, using the source rather than
+    /// the events to keep backslashes and underscores. If there is none in
+    /// the same paragraph, the `$` is just a dollar sign.
+    fn parse_math(&mut self) -> Result<Event<'a>, SidenoteError> {
+        let display = self.remaining_text.starts_with('$');
+        if display {
+            self.cycle_remaining_text();
+        }
+        let delimiter = if display { "$$" } else { "$" };
+        let opens = match self.remaining_text.chars().next() {
+            Some(c) => display || !c.is_whitespace(),
+            None => true
+        };
+        if self.escaped || !opens {
+            return Ok(Event::Text(Cow::from(delimiter)));
+        }
+
+        let mut tex = self.remaining_text.clone();
+        let offset = self.remaining_offset();
+        let mut read_ahead = vec![];
+        loop {
+            if let Some(end) = find_closing_math(&tex, display) {
+                self.remaining_text = tex[end + delimiter.len()..].to_string();
+                tex.truncate(end);
+                let math = latex_to_mathml(&tex, display)?;
+                return Ok(Event::InlineHtml(Cow::from(math)));
+            }
+            match self.next_raw_event() {
+                Some((event, start, end)) => {
+                    let inline = match event {
+                        Event::SoftBreak | Event::HardBreak => {
+                            tex.push('\n');
+                            true
+                        },
+                        Event::Text(_) | Event::InlineHtml(_) |
+                        Event::Start(Tag::Emphasis) | Event::End(Tag::Emphasis) |
+                        Event::Start(Tag::Strong) | Event::End(Tag::Strong) => {
+                            tex.push_str(self.raw(start, end));
+                            true
+                        },
+                        _ => false
+                    };
+                    read_ahead.push((event, start, end));
+                    self.set_span(start, end);
+                    if !inline {
+                        break;
+                    }
+                },
+                None => break
+            }
+        }
+        read_ahead.reverse();
+        self.lookahead.extend(read_ahead);
+        self.set_span(offset, offset + self.remaining_text.len());
+        Ok(Event::Text(Cow::from(delimiter)))
+    }
+
+    /// start a new numbered sidenote, whose opening brace, or footnote
+    /// reference, is at `offset`
+    pub fn open_sidenote(&mut self, offset: usize) -> Event<'a> {
+        self.in_sidenote_block = true;
+        self.sidenote_start = offset;
+        self.sidenotes.push("".to_string());
+        if self.as_footnotes {
+            Event::InlineHtml(Cow::from(format!(
+                "<sup class=\"footnote-ref\">{}</sup>", self.sidenotes.len())))
+        } else {
+            Event::InlineHtml(Cow::from(start_sidenote(self.sidenotes.len())))
+        }
+    }
+
+    pub fn close_sidenote(&mut self) -> Event<'a> {
+        self.in_sidenote_block = false;
+        if self.in_margin_note {
+            self.in_margin_note = false;
+            Event::InlineHtml(Cow::from("</span>"))
+        } else if self.as_footnotes {
+            Event::Text(Cow::from(""))
+        } else {
+            Event::InlineHtml(Cow::from("</span>"))
+        }
+    }
+
     pub fn parse_remaining_text<'b>(&'b mut self) -> Result<Event<'a>, SidenoteError> {
         // println!("remaining_text: {}", self.remaining_text);
+        let offset = self.remaining_offset();
         let first_char = self.cycle_remaining_text();
         match first_char {
+            // `\{` and `\}` are literal braces, and `\{{` a literal variable
+            '{' if self.escaped => Ok(self.parse_escaped_variable()),
+            '}' if self.escaped => Ok(Event::Text(Cow::from("}"))),
+            '{' if self.remaining_text.starts_with('{') => self.parse_variable(offset),
             '{' => {
                 if self.in_sidenote_block {
-                    Err(SidenoteError::Nested)
-                } else {
+                    Err(SidenoteError::Nested(self.source_position(offset)))
+                } else if self.remaining_text.starts_with('-') {
+                    // a margin note, `{-like this}`, has no number and
+                    // isn't listed with the sidenotes
+                    self.cycle_remaining_text();
                     self.in_sidenote_block = true;
-                    self.sidenotes.push("".to_string());
-                    Ok(Event::InlineHtml(Cow::from(START_SIDENOTE)))
+                    self.sidenote_start = offset;
+                    self.in_margin_note = true;
+                    self.margin_notes += 1;
+                    if self.as_footnotes {
+                        Ok(Event::InlineHtml(Cow::from(START_MARGIN_NOTE)))
+                    } else {
+                        Ok(Event::InlineHtml(Cow::from(start_margin_note(self.margin_notes))))
+                    }
+                } else {
+                    Ok(self.open_sidenote(offset))
                 }
             },
+            '$' => self.parse_math(),
             '}' => {
                 if self.in_sidenote_block {
-                    self.in_sidenote_block = false;
-                    Ok(Event::InlineHtml(Cow::from("</span>")))
+                    Ok(self.close_sidenote())
                 } else {
-                    Err(SidenoteError::NotMatched)
+                    Err(SidenoteError::NotMatched(self.source_position(offset)))
                 }
             },
             _ => {
@@ -116,7 +256,9 @@ mod tests {
     use regex::Regex;
     use std::borrow::Cow;
     use pulldown_cmark::{Event, Parser};
-    use super::{SidenoteParser, START_SIDENOTE};
+    use super::{SidenoteParser, start_sidenote, start_margin_note};
+    use sidenote_error::SidenoteError;
+    use parser::html_from_markdown;
 
 
     #[test]
@@ -132,7 +274,9 @@ mod tests {
         let text = "here is some text {with sidenotes}"; 
         let mut title: Option<String> = None;
         let mut sidenotes: Vec<String> = vec![];
-        let mut parser = SidenoteParser::new(Parser::new(""), &mut title, &mut sidenotes);
+        let mut headings = vec![];
+        let mut parser = SidenoteParser::new(Parser::new(""), &mut title, &mut sidenotes,
+                                             &mut headings);
         assert_eq!(parser.parse_first_sidenote(Cow::from(text)),
             Event::Text(Cow::from("here is some text ")));
     }
@@ -141,14 +285,70 @@ mod tests {
     fn can_parse_remaining() {
         let mut title: Option<String> = None;
         let mut sidenotes: Vec<String> = vec![];
-        let mut parser = SidenoteParser::new(Parser::new(""), &mut title, &mut sidenotes);
+        let mut headings = vec![];
+        let mut parser = SidenoteParser::new(Parser::new(""), &mut title, &mut sidenotes,
+                                             &mut headings);
         parser.remaining_text = String::from("some remaining { text");
         let mut event = parser.parse_remaining_text().unwrap();
         assert_eq!(event, Event::Text(Cow::from("some remaining ")));
         event = parser.parse_remaining_text().unwrap();
-        assert_eq!(event, Event::InlineHtml(Cow::from(START_SIDENOTE)));
+        assert_eq!(event, Event::InlineHtml(Cow::from(start_sidenote(1))));
         event = parser.parse_remaining_text().unwrap();
         assert_eq!(event, Event::Text(Cow::from(" text")));
         assert_eq!(parser.remaining_text, "");
     }
+
+    #[test]
+    fn can_parse_escaped_braces() {
+        let mut title: Option<String> = None;
+        let mut sidenotes: Vec<String> = vec![];
+        let mut headings = vec![];
+        {
+            let mut parser = SidenoteParser::new(Parser::new(""), &mut title, &mut sidenotes,
+                                             &mut headings);
+            parser.remaining_text = String::from("}");
+            parser.escaped = true;
+            assert_eq!(parser.parse_remaining_text().unwrap(), Event::Text(Cow::from("}")));
+            assert!(!parser.in_sidenote_block);
+        }
+        assert!(sidenotes.is_empty());
+
+        let md = r"The set \{1, 2\}{a note with \{braces\}} and `\{code\}`";
+        let parsed = html_from_markdown(md, "".to_string()).unwrap();
+        assert!(parsed.html.starts_with("<article>\n<p>The set {1, 2}<label"));
+        assert!(parsed.html.ends_with("and <code>\\{code\\}</code></p>\n</section></article>"));
+        assert_eq!(parsed.sidenotes, vec!["a note with {braces}"]);
+        assert!(html_from_markdown(r"An escaped \{ and a } brace", "".to_string()).is_err());
+    }
+
+    #[test]
+    fn can_parse_margin_notes() {
+        let mut title: Option<String> = None;
+        let mut sidenotes: Vec<String> = vec![];
+        let mut headings = vec![];
+        {
+            let mut parser = SidenoteParser::new(Parser::new(""), &mut title, &mut sidenotes,
+                                             &mut headings);
+            parser.remaining_text = String::from("a {-margin note} b");
+            let mut event = parser.parse_remaining_text().unwrap();
+            assert_eq!(event, Event::Text(Cow::from("a ")));
+            event = parser.parse_remaining_text().unwrap();
+            assert_eq!(event, Event::InlineHtml(Cow::from(start_margin_note(1))));
+            assert!(parser.in_margin_note);
+            event = parser.parse_remaining_text().unwrap();
+            assert_eq!(event, Event::Text(Cow::from("margin note")));
+            event = parser.parse_remaining_text().unwrap();
+            assert_eq!(event, Event::InlineHtml(Cow::from("</span>")));
+            assert!(!parser.in_margin_note);
+
+            parser.remaining_text = String::from("{-nested {sidenote}}");
+            parser.parse_remaining_text().unwrap();
+            parser.parse_remaining_text().unwrap();
+            match parser.parse_remaining_text() {
+                Err(SidenoteError::Nested(_)) => (),
+                other => panic!("Expected a nesting error, got {:?}", other)
+            }
+        }
+        assert!(sidenotes.is_empty());
+    }
 }
@@ -0,0 +1,83 @@
+use std::time::SystemTime;
+
+use url::Url;
+
+use toc::IndexedBlogPost;
+
+
+#[derive(Serialize)]
+struct SitemapUrl {
+    loc: String,
+    lastmod: SystemTime
+}
+
+
+/// Data for rendering `sitemap.xml`: the table of contents followed by
+/// every post, with absolute URLs.
+#[derive(Serialize)]
+pub struct SitemapData {
+    urls: Vec<SitemapUrl>
+}
+
+
+fn absolute(home: &Url, path: &str) -> String {
+    let mut url = home.clone();
+    url.set_path(path);
+    url.to_string()
+}
+
+
+impl SitemapData {
+    pub fn example() -> Self {
+        SitemapData{
+            urls: vec![SitemapUrl{
+                loc: "https://example.com/".to_string(),
+                lastmod: SystemTime::now()
+            }]
+        }
+    }
+
+    /// the table of contents changes whenever any of the posts do,
+    /// so it takes the most recent `last_updated`
+    pub fn new(home: &Url, index_url: &str, posts: &[IndexedBlogPost]) -> Self {
+        let index_updated = posts.iter().map(|p| p.last_updated).max()
+            .unwrap_or_else(SystemTime::now);
+        let mut urls = vec![SitemapUrl{
+            loc: absolute(home, index_url),
+            lastmod: index_updated
+        }];
+        for post in posts.iter().rev() {
+            urls.push(SitemapUrl{
+                loc: absolute(home, &post.post_url),
+                lastmod: post.last_updated
+            });
+        }
+        SitemapData{urls}
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use url::Url;
+    use toc::IndexedBlogPost;
+    use super::SitemapData;
+
+    #[test]
+    fn can_list_urls() {
+        let home = Url::parse("https://example.com").unwrap();
+        let mut posts = vec![IndexedBlogPost::example(), IndexedBlogPost::example()];
+        posts[0].post_url = "/blog/old/".to_string();
+        posts[0].last_updated = UNIX_EPOCH + Duration::from_secs(120);
+        posts[1].post_url = "/blog/new/".to_string();
+        posts[1].last_updated = UNIX_EPOCH + Duration::from_secs(60);
+        let sitemap = SitemapData::new(&home, "/blog/", &posts);
+        let locs: Vec<&str> = sitemap.urls.iter().map(|u| u.loc.as_str()).collect();
+        assert_eq!(locs, vec!["https://example.com/blog/",
+                              "https://example.com/blog/new/",
+                              "https://example.com/blog/old/"]);
+        assert_eq!(sitemap.urls[0].lastmod, posts[0].last_updated);
+        assert_eq!(sitemap.urls[1].lastmod, posts[1].last_updated);
+    }
+}
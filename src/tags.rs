@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+
+use toc::IndexedBlogPost;
+
+
+/// turn a tag into something that can be used in a URL,
+/// e.g. "Machine Learning" becomes "machine-learning"
+pub fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
+
+
+#[derive(Serialize, Clone)]
+pub struct Tag {
+    pub name: String,
+    pub slug: String,
+    url: String,
+    count: usize
+}
+
+
+/// Data for rendering the tags template. On the overview page `tag` is
+/// empty and `index` lists no posts; on the page of a single tag, `index`
+/// lists the posts with that tag, newest first.
+#[derive(Serialize)]
+pub struct TagData<'a> {
+    index_url: String,
+    tag: Option<Tag>,
+    tags: Vec<Tag>,
+    index: Vec<&'a IndexedBlogPost>
+}
+
+
+/// group the posts by tag. Tags are compared by their slug, so that
+/// "Rust" and "rust" end up on the same page.
+pub fn collect_tags<'a>(posts: &'a [IndexedBlogPost], index_url: &str)
+    -> Vec<(Tag, Vec<&'a IndexedBlogPost>)> {
+    let mut by_slug: BTreeMap<String, (Tag, Vec<&'a IndexedBlogPost>)> = BTreeMap::new();
+    for post in posts.iter().rev() {
+        for name in post.meta.tags.iter() {
+            let slug = slugify(name);
+            if slug.is_empty() {
+                continue;
+            }
+            let entry = by_slug.entry(slug.clone()).or_insert_with(|| (Tag{
+                name: name.to_string(),
+                url: format!("{}tags/{}/", index_url, slug),
+                slug,
+                count: 0
+            }, vec![]));
+            if !entry.1.iter().any(|p| p.post_url == post.post_url) {
+                entry.0.count += 1;
+                entry.1.push(post);
+            }
+        }
+    }
+    by_slug.into_values().collect()
+}
+
+
+impl<'a> TagData<'a> {
+
+    pub fn example(post: &'a IndexedBlogPost) -> Self {
+        let tag = Tag{
+            name: "Example".to_string(),
+            slug: "example".to_string(),
+            url: "/tags/example/".to_string(),
+            count: 1
+        };
+        TagData{
+            index_url: "/".to_string(),
+            tag: Some(tag.clone()),
+            tags: vec![tag],
+            index: vec![post]
+        }
+    }
+
+    pub fn overview(tags: &[(Tag, Vec<&'a IndexedBlogPost>)], index_url: &str) -> Self {
+        TagData{
+            index_url: index_url.to_string(),
+            tag: None,
+            tags: tags.iter().map(|t| t.0.clone()).collect(),
+            index: vec![]
+        }
+    }
+
+    pub fn for_tag(tags: &[(Tag, Vec<&'a IndexedBlogPost>)], index_url: &str,
+                   i: usize) -> Self {
+        TagData{
+            index_url: index_url.to_string(),
+            tag: Some(tags[i].0.clone()),
+            tags: tags.iter().map(|t| t.0.clone()).collect(),
+            index: tags[i].1.clone()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use toc::IndexedBlogPost;
+    use super::{slugify, collect_tags};
+
+    #[test]
+    fn can_slugify() {
+        assert_eq!(slugify("Machine Learning"), "machine-learning");
+        assert_eq!(slugify(" C++ & Rust! "), "c-rust");
+        assert_eq!(slugify("Zürich"), "zürich");
+        assert_eq!(slugify("?!"), "");
+    }
+
+    #[test]
+    fn can_collect_tags() {
+        let mut posts = vec![IndexedBlogPost::example(), IndexedBlogPost::example()];
+        posts[0].post_url = "/blog/old/".to_string();
+        posts[0].meta.tags = vec!["Rust".to_string(), "rust".to_string()];
+        posts[1].post_url = "/blog/new/".to_string();
+        posts[1].meta.tags = vec!["rust".to_string(), "Clogs".to_string()];
+        let tags = collect_tags(&posts, "/blog/");
+        assert_eq!(tags.len(), 2);
+        assert_eq!(tags[0].0.slug, "clogs");
+        assert_eq!(tags[0].0.url, "/blog/tags/clogs/");
+        assert_eq!(tags[1].0.name, "rust");
+        assert_eq!(tags[1].0.count, 2);
+        let urls: Vec<&str> = tags[1].1.iter().map(|p| p.post_url.as_str()).collect();
+        assert_eq!(urls, vec!["/blog/new/", "/blog/old/"]);
+    }
+}
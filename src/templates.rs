@@ -1,7 +1,6 @@
 use std::fmt;
 use std::fs;
 use std::io;
-use std::str::from_utf8;
 use std::time::SystemTime;
 use handlebars::{Handlebars, no_escape};
 use handlebars::{RenderContext, Helper, Context, HelperResult, Output, RenderError};
@@ -10,14 +9,24 @@ use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
 
 use rss::RssData;
+use tags::TagData;
+use sitemap::SitemapData;
+use toc::IndexedBlogPost;
+use hash::text_hash;
 
 
 pub const TOC_TEMPLATE: &[u8]  = include_bytes!("../templates/toc.html");
 pub const POST_TEMPLATE: &[u8]  = include_bytes!("../templates/post.html");
 pub const RSS_TEMPLATE: &[u8]  = include_bytes!("../templates/rss.xml");
+pub const TAGS_TEMPLATE: &[u8]  = include_bytes!("../templates/tags.html");
+pub const ATOM_TEMPLATE: &[u8]  = include_bytes!("../templates/atom.xml");
+pub const SITEMAP_TEMPLATE: &[u8]  = include_bytes!("../templates/sitemap.xml");
 
 pub const PATH_POST: &str = ".post_template.html";
 pub const PATH_INDEX: &str = ".index_template.html";
+pub const PATH_TAGS: &str = ".tags_template.html";
+pub const PATH_RSS: &str = ".rss_template.xml";
+pub const PATH_TEMPLATE_HASHES: &str = ".templates.csv";
 
 #[derive(Debug, Copy, Clone)]
 pub enum ErrorKind {
@@ -48,10 +57,27 @@ impl TemplateError {
 }
 
 
+/// Hashes of the templates for the posts, the table of contents, the
+/// tag pages and the RSS feed, persisted next to the index. When they differ from the
+/// templates being loaded, `sync` writes the outputs of those templates
+/// again.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateHashes {
+    pub post: String,
+    pub index: String,
+    pub tags: String,
+    pub rss: String
+}
+
+
 pub struct AllTemplates {
     pub post: Handlebars,
     pub index: Handlebars,
     pub rss: Handlebars,
+    pub tags: Handlebars,
+    pub atom: Handlebars,
+    pub sitemap: Handlebars,
+    pub hashes: TemplateHashes
 }
 
 
@@ -96,7 +122,10 @@ impl AllTemplates {
 
         let format_str = match h.param(1) {
             None => "%d %B %Y at %H:%M UTC", // display
-            _ => "%a, %d %b %Y %T GMT", // RSS
+            Some(p) => match p.value().as_str() {
+                Some("atom") => "%Y-%m-%dT%H:%M:%SZ", // Atom (RFC 3339)
+                _ => "%a, %d %b %Y %T GMT", // RSS
+            }
         };
 
         let datetime = DateTime::<Utc>::from(stime);
@@ -137,57 +166,74 @@ impl AllTemplates {
         Ok(())
     }
 
-    fn make(path: &str, fallback: &[u8]) -> Result<Handlebars, TemplateError> {
+    // the template, with the hash of its source
+    fn make(path: &str, fallback: &[u8]) -> Result<(Handlebars, String), TemplateError> {
         let template_str = AllTemplates::read_template(path, fallback)?;
         let template = AllTemplates::make_template(&template_str, path)?;
-        Ok(template)
+        Ok((template, text_hash(&template_str)))
     }
 
     pub fn make_from_paths(path_post: Option<String>, 
-                           path_index: Option<String>) -> Result<Self, TemplateError> {
+                           path_index: Option<String>,
+                           path_tags: Option<String>,
+                           path_rss: Option<String>) -> Result<Self, TemplateError> {
         let post_path = path_post.unwrap_or(PATH_POST.to_string());
         let index_path = path_index.unwrap_or(PATH_INDEX.to_string());
-        let mut post_template = AllTemplates::make(&post_path, POST_TEMPLATE)?;
+        let tags_path = path_tags.unwrap_or(PATH_TAGS.to_string());
+        let rss_path = path_rss.unwrap_or(PATH_RSS.to_string());
+        let (mut post_template, post_hash) = AllTemplates::make(&post_path, POST_TEMPLATE)?;
         post_template.register_escape_fn(no_escape);
 
-        let rss = match AllTemplates::make_template(match from_utf8(RSS_TEMPLATE) {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(TemplateError{
-                msg: format!("Couldn't read rss template: {}", e),
-                kind: ErrorKind::InvalidSyntax
-            });}
-        }, "rss-path") {
-            Ok(h) => h,
-            Err(e) => {
-                return Err(TemplateError{
-                msg: format!("Couldn't read rss template: {}", e),
-                kind: ErrorKind::InvalidSyntax
-            });}
-        };
+        let (rss, rss_hash) = AllTemplates::make(&rss_path, RSS_TEMPLATE)?;
 
         let rss_test = RssData::example();
 
-        AllTemplates::validate::<RssData>(&rss, &rss_test, "rss-path")?;
+        AllTemplates::validate::<RssData>(&rss, &rss_test, &rss_path)?;
+
+        let atom = AllTemplates::make_template(&String::from_utf8_lossy(ATOM_TEMPLATE),
+                                               "atom-path")?;
+        AllTemplates::validate::<RssData>(&atom, &rss_test, "atom-path")?;
+
+        let sitemap = AllTemplates::make_template(&String::from_utf8_lossy(SITEMAP_TEMPLATE),
+                                                  "sitemap-path")?;
+        AllTemplates::validate::<SitemapData>(&sitemap, &SitemapData::example(),
+                                              "sitemap-path")?;
+
+        let (tags, tags_hash) = AllTemplates::make(&tags_path, TAGS_TEMPLATE)?;
+        let example_post = IndexedBlogPost::example();
+        AllTemplates::validate::<TagData>(&tags, &TagData::example(&example_post),
+                                          &tags_path)?;
+
+        let (index, index_hash) = AllTemplates::make(&index_path, TOC_TEMPLATE)?;
 
         Ok(AllTemplates{
             post: post_template,
-            index: AllTemplates::make(&index_path, TOC_TEMPLATE)?,
-            rss
+            index,
+            rss,
+            tags,
+            atom,
+            sitemap,
+            hashes: TemplateHashes{post: post_hash, index: index_hash, tags: tags_hash,
+                                   rss: rss_hash}
         })
     }
 
     pub fn new() -> Result<Self, TemplateError> {
-        AllTemplates::make_from_paths(None, None)
+        AllTemplates::make_from_paths(None, None, None, None)
     }
 }
 
-impl From<(Handlebars, Handlebars, Handlebars)> for AllTemplates {
-    fn from(templates: (Handlebars, Handlebars, Handlebars)) -> Self {
+impl From<(Handlebars, Handlebars, Handlebars, Handlebars, Handlebars, Handlebars)> for AllTemplates {
+    fn from(templates: (Handlebars, Handlebars, Handlebars, Handlebars, Handlebars,
+                        Handlebars)) -> Self {
         AllTemplates{
             post: templates.0, 
             index: templates.1,
-            rss: templates.2
+            rss: templates.2,
+            tags: templates.3,
+            atom: templates.4,
+            sitemap: templates.5,
+            hashes: TemplateHashes::default()
         }
     }
 }
@@ -195,9 +241,13 @@ impl From<(Handlebars, Handlebars, Handlebars)> for AllTemplates {
 
 #[cfg(test)]
 mod tests {
-    use toc::{Blog, IndexedBlogPost};
+    use std::env;
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use rss::RssData;
+    use toc::{BlogRevIndex, IndexedBlogPost};
     use parser::PostData;
-    use std::path::PathBuf;
+    use headings::Heading;
 
     use super::AllTemplates;
 
@@ -206,10 +256,59 @@ mod tests {
         let templates = AllTemplates::new().expect("Can't get templates");
         let article = "some article";
         let test_post = PostData::new(&article);
-        let mut test_index = Blog::new(PathBuf::from("/example")).unwrap();
-        test_index.push(IndexedBlogPost::example());
-        assert!(templates.validate_both::<PostData<'static>, Blog>(
-                &test_post, &test_index).is_ok());
+        let posts = vec![IndexedBlogPost::example()];
+        let test_index = BlogRevIndex::paginate(&posts, "/example/", None);
+        assert!(templates.validate_both::<PostData<'static>, BlogRevIndex>(
+                &test_post, &test_index[0]).is_ok());
     }
-}
 
+    #[test]
+    fn can_override_rss_template() {
+        let good_path = env::temp_dir().join("wellington_rss_template.xml");
+        let bad_path = env::temp_dir().join("wellington_bad_rss_template.xml");
+        fs::write(&good_path, "<rss>{{core_data.title}}{{#each posts}}{{as-date last_updated rss}}{{/each}}</rss>")
+            .expect("Can't write template");
+        fs::write(&bad_path, "<rss>{{#each posts}}{{as-date missing rss}}{{/each}}</rss>")
+            .expect("Can't write template");
+        let templates = AllTemplates::make_from_paths(None, None, None,
+                                                      Some(good_path.to_string_lossy().to_string()))
+            .expect("Can't get templates");
+        let rendered = templates.rss.render("t1", &RssData::example()).unwrap();
+        assert!(rendered.starts_with("<rss>bla"));
+        let defaults = AllTemplates::new().unwrap();
+        assert!(templates.hashes.rss != defaults.hashes.rss);
+        assert_eq!(templates.hashes.post, defaults.hashes.post);
+        assert_eq!(templates.hashes.tags, defaults.hashes.tags);
+        assert!(AllTemplates::make_from_paths(None, None, None,
+                                              Some(bad_path.to_string_lossy().to_string())).is_err());
+        fs::remove_file(&good_path).unwrap();
+        fs::remove_file(&bad_path).unwrap();
+    }
+
+    #[test]
+    fn can_render_nested_toc() {
+        let templates = AllTemplates::new().expect("Can't get templates");
+        let mut post = IndexedBlogPost::example();
+        let headings = (2..7).map(|level| Heading::new(&format!("h{}", level), "Heading", level))
+            .collect();
+        let data = PostData::from(("", &mut post, "/", "/post/".to_string(), vec![], headings));
+        let rendered = templates.post.render("t1", &data).unwrap();
+        for level in 2..7 {
+            assert!(rendered.contains(&format!("<a href=\"#h{}\">Heading</a>", level)));
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Dated {
+        t: SystemTime
+    }
+
+    #[test]
+    fn can_format_dates() {
+        let template = AllTemplates::make_template(
+            r#"{{as-date t}}|{{as-date t rss}}|{{as-date t "atom"}}"#, "date-path")
+            .expect("Can't make template");
+        let rendered = template.render("t1", &Dated{t: UNIX_EPOCH}).unwrap();
+        assert_eq!(rendered, "01 January 1970 at 00:00 UTC|Thu, 01 Jan 1970 00:00:00 GMT|1970-01-01T00:00:00Z");
+    }
+}
@@ -5,10 +5,24 @@ use std::path::PathBuf;
 use std::time::SystemTime;
 use csv::{WriterBuilder, ReaderBuilder};
 use handlebars::Handlebars;
+use serde_json;
 
-use parser::{html_from_markdown, PostData};
-use templates::{AllTemplates, TemplateError, PATH_POST, PATH_INDEX};
-use rss::{CoreData, RSSError, RssData};
+use parser::{html_from_markdown_with_variables, feed_html_from_markdown_with_variables,
+             ParsedMarkdown, PostData};
+use variables::Variables;
+use front_matter::{split_front_matter, FrontMatter};
+use templates::{AllTemplates, TemplateError, TemplateHashes, PATH_POST, PATH_INDEX,
+                PATH_TAGS, PATH_RSS, PATH_TEMPLATE_HASHES};
+use rss::{CoreData, RSSError, RssData, JsonFeed, FEED_LENGTH};
+use tags::{collect_tags, TagData};
+use sitemap::SitemapData;
+use highlight::css_for_theme;
+use hash::content_hash;
+
+
+/// the drafts and scheduled posts last rendered for previewing, next to
+/// the index
+pub const PATH_PREVIEWS: &str = ".previews.csv";
 
 
 #[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
@@ -20,10 +34,36 @@ pub struct IndexedBlogPost {
     pub first_published: SystemTime,
     #[serde(skip)]
     checked: bool,
-    pub title: Option<String>
+    #[serde(skip)]
+    hash: String,
+    pub title: Option<String>,
+    pub meta: FrontMatter
 } 
 
 
+/// A row of the index file. The front matter isn't persisted: it is
+/// read again from `index.md` on every sync. Indexes written before the
+/// content was hashed lack the hash.
+#[derive(Deserialize, Serialize)]
+struct IndexEntry {
+    post_url: String,
+    last_updated: SystemTime,
+    first_published: SystemTime,
+    title: Option<String>,
+    #[serde(default)]
+    hash: String
+}
+
+
+/// A row of the previews file: a draft or scheduled post, by the URL of
+/// its directory, with the hash of its content when it was last rendered.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+struct PreviewEntry {
+    files_url: String,
+    hash: String
+}
+
+
 #[derive(Debug)]
 struct BlogPost {
     path: PathBuf,
@@ -41,6 +81,12 @@ fn post_url_from_path(path: &PathBuf) -> String {
         },
         None => ""
     };
+    slug_url(path, post_name)
+}
+
+
+// the url of the blogpost at `path` when published under `slug`
+fn slug_url(path: &PathBuf, slug: &str) -> String {
     let blog_name = match path.parent() {
         Some(p) => match p.file_name() {
             Some(s) => match s.to_str() {
@@ -51,7 +97,15 @@ fn post_url_from_path(path: &PathBuf) -> String {
         },
         None => ""
     };
-    format!("/{}/{}/", blog_name, post_name)
+    format!("/{}/{}/", blog_name, slug)
+}
+
+
+/// a slug names a directory of its own in the blog, other than those
+/// `sync` writes the tag pages and the pages of the table of contents to
+fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty() && !slug.starts_with('.') && slug != "tags" && slug != "page"
+        && !slug.contains(|c: char| c == '/' || c == '\\' || c.is_whitespace())
 }
 
 
@@ -65,7 +119,40 @@ impl From<BlogPost> for IndexedBlogPost {
             last_updated: post.last_updated,
             first_published: post.last_updated,
             checked: false,
-            title: None
+            hash: String::new(),
+            title: None,
+            meta: FrontMatter::default()
+        }
+    }
+}
+
+
+impl From<IndexEntry> for IndexedBlogPost {
+
+    fn from(entry: IndexEntry) -> Self {
+        IndexedBlogPost {
+            path: PathBuf::new(),
+            post_url: entry.post_url,
+            last_updated: entry.last_updated,
+            first_published: entry.first_published,
+            checked: false,
+            hash: entry.hash,
+            title: entry.title,
+            meta: FrontMatter::default()
+        }
+    }
+}
+
+
+impl<'a> From<&'a IndexedBlogPost> for IndexEntry {
+
+    fn from(post: &'a IndexedBlogPost) -> Self {
+        IndexEntry {
+            post_url: post.post_url.clone(),
+            last_updated: post.last_updated,
+            first_published: post.first_published,
+            title: post.title.clone(),
+            hash: post.hash.clone()
         }
     }
 }
@@ -84,6 +171,87 @@ impl IndexedBlogPost {
         self.title = title.clone();
     }
 
+    /// a date in the front matter, or else the scheduled date of
+    /// publication, overrides the time of first publication, and a slug
+    /// the URL of the post
+    pub fn set_front_matter(&mut self, meta: FrontMatter) {
+        if let Some(date) = meta.date.or(meta.publish_at) {
+            self.first_published = date;
+        }
+        if !self.path.as_os_str().is_empty() {
+            self.post_url = match meta.slug {
+                Some(ref slug) => slug_url(&self.path, slug),
+                None => post_url_from_path(&self.path)
+            };
+        }
+        self.meta = meta;
+    }
+
+    /// the URL of the post directory, with the markdown and the files it
+    /// links to. It differs from `post_url` if the post has a slug.
+    pub fn files_url(&self) -> String {
+        post_url_from_path(&self.path)
+    }
+
+    // the directory the post is rendered to, named after the slug if any
+    fn output_dir(&self) -> PathBuf {
+        match (&self.meta.slug, self.path.parent()) {
+            (Some(slug), Some(blog_path)) => blog_path.join(slug),
+            _ => self.path.clone()
+        }
+    }
+
+    /// whether the table of contents, the feeds or the tag pages show
+    /// this post differently than `other`
+    fn listed_differently(&self, other: &IndexedBlogPost) -> bool {
+        self.title != other.title || self.first_published != other.first_published
+            || self.meta.tags != other.meta.tags
+            || self.meta.description != other.meta.description
+    }
+
+    /// drafts are marked in the front matter, or by starting
+    /// the name of the post directory with `_draft`
+    pub fn is_draft(&self) -> bool {
+        let in_draft_dir = match self.path.file_name().and_then(|s| s.to_str()) {
+            Some(name) => name.starts_with("_draft"),
+            None => false
+        };
+        self.meta.draft || in_draft_dir
+    }
+
+    /// posts with a `publish_at` date in the future are held back
+    pub fn is_scheduled(&self, now: SystemTime) -> bool {
+        match self.meta.publish_at {
+            Some(t) => t > now,
+            None => false
+        }
+    }
+
+    // read only the front matter and hash the content, for deciding what
+    // to do with a post
+    fn read_front_matter(&mut self) -> Result<(), BlogError> {
+        let input_filename = self.get_filename_path("index.md")?;
+        let input = match fs::read_to_string(&input_filename) {
+            Ok(s) => s,
+            _ => {
+                return Err(BlogError::ReadError(input_filename));
+            }
+        };
+        self.hash = content_hash(&self.path, &input);
+        match split_front_matter(&input) {
+            Ok((ref meta, _)) if meta.slug.as_ref().is_some_and(|s| !is_valid_slug(s)) => {
+                Err(BlogError::ConvertError(format!(
+                    "{}: the slug {:?} can't name a directory of the blog", input_filename,
+                    meta.slug.as_ref().unwrap())))
+            },
+            Ok((meta, _)) => {
+                self.set_front_matter(meta);
+                Ok(())
+            },
+            Err(err) => Err(BlogError::ConvertError(format!("{}: {}", input_filename, err)))
+        }
+    }
+
     fn get_filename_path(&self, file: &str) -> Result<String, BlogError> {
         let mut input_path = self.path.clone();
         input_path.push(file);
@@ -95,37 +263,68 @@ impl IndexedBlogPost {
         }
     }
 
-    fn convert(&mut self, template: &Handlebars, index_url: &str) -> Result<(), BlogError> {
+    // the post URL in the markdown is the link prefix, which is absolute
+    // in the feeds
+    fn parse(&self, link_prefix: String, for_feed: bool, variables: &Variables)
+        -> Result<ParsedMarkdown, BlogError> {
         let input_filename = self.get_filename_path("index.md")?;
-        let output_filename = self.get_filename_path("index.html")?;
         if let Ok(input) = fs::read_to_string(&input_filename) {
-            let output = match html_from_markdown(&input, 
-                                                  self.post_url.clone()) {
-                Ok(ht) => ht,
-                Err(err) => {
-                    return Err(BlogError::ConvertError(format!("{}", err)));
-                }
-            };
-            self.title = output.title;
-            let post_url = self.post_url.clone();
-            let data = PostData::from((output.html.as_str(), self, index_url, post_url, 
-                                       output.sidenotes));
-            let rendered = match data.render(template) {
-                Ok(ht) => ht,
-                Err(err) => {
-                    return Err(BlogError::ConvertError(format!("{}", err)));
-                }
-            };
-            match fs::write(&output_filename, rendered) {
-                Err(_) => {
-                    return Err(BlogError::WriteError(output_filename));
-                },
-                _ => ()
+            let mut variables = variables.clone();
+            variables.insert("post_url".to_string(), link_prefix.clone());
+            let parsed = if for_feed {
+                feed_html_from_markdown_with_variables(&input, link_prefix, &variables)
+            } else {
+                html_from_markdown_with_variables(&input, link_prefix, &variables)
             };
+            match parsed {
+                Ok(ht) => Ok(ht),
+                Err(err) => Err(BlogError::ConvertError(format!("{}: {}", input_filename, err)))
+            }
         } else {
-            return Err(BlogError::ReadError(input_filename))
+            Err(BlogError::ReadError(input_filename))
+        }
+    }
+
+    /// the article on its own, without the post template, as required by
+    /// the feeds: sidenotes become footnotes at the end of the article.
+    /// Relative links are prefixed with `link_prefix`.
+    pub fn render_article(&self, link_prefix: String, variables: &Variables)
+        -> Result<ParsedMarkdown, BlogError> {
+        self.parse(link_prefix, true, variables)
+    }
+
+    fn convert(&mut self, template: &Handlebars, index_url: &str, variables: &Variables)
+        -> Result<(), BlogError> {
+        let output = self.parse(self.files_url(), false, variables)?;
+        self.title = output.title;
+        self.set_front_matter(output.front_matter);
+        let output_dir = self.output_dir();
+        let output_filename = output_dir.join("index.html").to_string_lossy().to_string();
+        if output_dir != self.path {
+            // don't overwrite another post
+            if output_dir.join("index.md").exists() {
+                return Err(BlogError::ConvertError(format!(
+                    "{}: the slug is the directory of another post", self.path.display())));
+            }
+            if fs::create_dir_all(&output_dir).is_err() {
+                return Err(BlogError::WriteError(output_filename));
+            }
+            // rendered before the post had a slug
+            let _ = fs::remove_file(self.path.join("index.html"));
+        }
+        let post_url = self.files_url();
+        let data = PostData::from((output.html.as_str(), self, index_url, post_url, 
+                                   output.sidenotes, output.headings));
+        let rendered = match data.render(template) {
+            Ok(ht) => ht,
+            Err(err) => {
+                return Err(BlogError::ConvertError(format!("{}", err)));
+            }
+        };
+        match fs::write(&output_filename, rendered) {
+            Err(_) => Err(BlogError::WriteError(output_filename)),
+            _ => Ok(())
         }
-        Ok(())
     }
 
 }
@@ -137,19 +336,56 @@ pub struct Blog {
     path: PathBuf,
     pub index_url: String,
     #[serde(skip)]
-    templates: AllTemplates
+    templates: AllTemplates,
+    #[serde(skip)]
+    previews: Vec<PreviewEntry>
 }
 
 
 #[derive(Serialize)]
-struct BlogRevIndex<'a> {
-    index: Vec<&'a IndexedBlogPost>
+pub struct BlogRevIndex<'a> {
+    index: Vec<&'a IndexedBlogPost>,
+    index_url: &'a str,
+    page: usize,
+    total_pages: usize,
+    prev_url: Option<String>,
+    next_url: Option<String>
 } // reversed index, for rendering
 
 
+// the first page is the index itself, the others are at page/<n>/
+fn page_url(index_url: &str, page: usize) -> String {
+    if page == 1 {
+        index_url.to_string()
+    } else {
+        format!("{}page/{}/", index_url, page)
+    }
+}
+
+
 impl<'a> BlogRevIndex<'a> {
-    fn new(index: &'a [IndexedBlogPost]) -> Self {
-        BlogRevIndex{index: index.iter().rev().collect()}
+    /// split the reversed index into pages of `per_page` posts each.
+    /// Without `per_page`, all posts go on a single page.
+    pub fn paginate(index: &'a [IndexedBlogPost], index_url: &'a str,
+                per_page: Option<usize>) -> Vec<Self> {
+        let posts: Vec<&'a IndexedBlogPost> = index.iter().rev().collect();
+        let per_page = match per_page {
+            Some(n) if n > 0 => n,
+            _ => posts.len().max(1)
+        };
+        let total_pages = posts.len().div_ceil(per_page).max(1);
+        (1..=total_pages).map(|page| BlogRevIndex{
+            index: posts.iter()
+                .skip((page - 1) * per_page)
+                .take(per_page)
+                .cloned()
+                .collect(),
+            index_url,
+            page,
+            total_pages,
+            prev_url: if page > 1 { Some(page_url(index_url, page - 1)) } else { None },
+            next_url: if page < total_pages { Some(page_url(index_url, page + 1)) } else { None }
+        }).collect()
     }
 }
 
@@ -164,6 +400,11 @@ pub enum BlogError {
     WriteIndexError(String),
     WriteTocError(String),
     WriteRssError(String),
+    WriteAtomError(String),
+    WriteJsonFeedError(String),
+    WriteSitemapError(String),
+    WriteHighlightError(String),
+    WriteTagsError(String),
     NoInit,
     InitWrite,
     InitTemplate(TemplateError),
@@ -185,6 +426,11 @@ impl fmt::Display for BlogError {
             BlogError::ReadIndexError(err) => write!(f, "Encountered an error while reading the index: {}", err),
             BlogError::WriteTocError(err) => write!(f, "Couldn't write table of contents {}", err),
             BlogError::WriteRssError(err) => write!(f, "Couldn't write rss feed {}", err),
+            BlogError::WriteAtomError(err) => write!(f, "Couldn't write atom feed {}", err),
+            BlogError::WriteJsonFeedError(err) => write!(f, "Couldn't write json feed {}", err),
+            BlogError::WriteSitemapError(err) => write!(f, "Couldn't write sitemap {}", err),
+            BlogError::WriteHighlightError(err) => write!(f, "Couldn't write highlighting stylesheet {}", err),
+            BlogError::WriteTagsError(err) => write!(f, "Couldn't write tag pages {}", err),
             BlogError::NoInit => write!(f, "Attempting to sync an uninitialised blog. Please call `init` first"),
             BlogError::InitWrite => write!(f, "Couldn't initialise blog. Do you have write permission?"),
             BlogError::InitTemplate(e) => 
@@ -211,7 +457,7 @@ impl Blog {
                 None => ""
             });
         }
-        let blog = Blog{path, index: vec![], index_url, templates};
+        let blog = Blog{path, index: vec![], index_url, templates, previews: vec![]};
         blog.validate_templates()?;
         Ok(blog)
     }
@@ -223,8 +469,9 @@ impl Blog {
     fn validate_templates(&self) -> Result<(), TemplateError> {
         let article = "some article";
         let test_post = PostData::new(&article);
-        self.templates.validate_both::<PostData<'static>, Blog>(
-            &test_post, &self)
+        let test_index = BlogRevIndex::paginate(&self.index, &self.index_url, None);
+        self.templates.validate_both::<PostData<'static>, BlogRevIndex>(
+            &test_post, &test_index[0])
     }
 
     fn set_templates(&mut self, templates: AllTemplates) {
@@ -236,6 +483,16 @@ impl Blog {
         index_path
     }
 
+    fn get_template_hashes_path(&self) -> PathBuf {
+        let mut index_path = self.path.clone(); index_path.push(PATH_TEMPLATE_HASHES);
+        index_path
+    }
+
+    fn get_previews_path(&self) -> PathBuf {
+        let mut index_path = self.path.clone(); index_path.push(PATH_PREVIEWS);
+        index_path
+    }
+
     fn get_toc_path(&self) -> PathBuf {
         let mut index_path = self.path.clone(); index_path.push("index.html");
         index_path
@@ -246,6 +503,36 @@ impl Blog {
         index_path
     }
 
+    fn get_atom_path(&self) -> PathBuf {
+        let mut index_path = self.path.clone(); index_path.push("atom.xml");
+        index_path
+    }
+
+    fn get_json_feed_path(&self) -> PathBuf {
+        let mut index_path = self.path.clone(); index_path.push("feed.json");
+        index_path
+    }
+
+    fn get_sitemap_path(&self) -> PathBuf {
+        let mut index_path = self.path.clone(); index_path.push("sitemap.xml");
+        index_path
+    }
+
+    fn get_highlight_path(&self) -> PathBuf {
+        let mut index_path = self.path.clone(); index_path.push("highlight.css");
+        index_path
+    }
+
+    fn get_pages_path(&self) -> PathBuf {
+        let mut index_path = self.path.clone(); index_path.push("page");
+        index_path
+    }
+
+    fn get_tags_path(&self) -> PathBuf {
+        let mut index_path = self.path.clone(); index_path.push("tags");
+        index_path
+    }
+
     fn load(&mut self) -> Result<(), BlogError> {
         let reader = match ReaderBuilder::new()
             .has_headers(false)
@@ -257,18 +544,55 @@ impl Blog {
             } 
         };
 
-        for post in reader.into_deserialize() {
-            self.index.push(match post {
-                Ok(p) => p,
+        for entry in reader.into_deserialize::<IndexEntry>() {
+            self.index.push(match entry {
+                Ok(e) => IndexedBlogPost::from(e),
                 Err(e) => {
                     return Err(BlogError::ReadIndexError(
                         format!("Could not parse index file: {:?}", e.kind())));
                 }
             });
         }
+        self.previews = self.load_previews();
         Ok(())      
     }
 
+    // the drafts and scheduled posts as of the last sync. Without the
+    // file, as before previews were tracked, they are all rendered again.
+    fn load_previews(&self) -> Vec<PreviewEntry> {
+        match ReaderBuilder::new().has_headers(false).from_path(self.get_previews_path()) {
+            Ok(reader) => reader.into_deserialize::<PreviewEntry>()
+                .filter_map(|e| e.ok())
+                .collect(),
+            _ => vec![]
+        }
+    }
+
+    // the hash of the draft or scheduled post at `files_url` when it was
+    // last rendered
+    fn preview_hash(&self, files_url: &str) -> Option<&str> {
+        self.previews.iter()
+            .find(|preview| preview.files_url == files_url)
+            .map(|preview| preview.hash.as_str())
+    }
+
+    fn set_preview(&mut self, files_url: String, hash: String) {
+        self.remove_preview(&files_url);
+        self.previews.push(PreviewEntry{files_url, hash});
+    }
+
+    fn remove_preview(&mut self, files_url: &str) {
+        self.previews.retain(|preview| preview.files_url != files_url);
+    }
+
+    // the hashes of the templates as of the last sync, if any
+    fn load_template_hashes(&self) -> Option<TemplateHashes> {
+        let reader = ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(self.get_template_hashes_path()).ok()?;
+        reader.into_deserialize::<TemplateHashes>().next()?.ok()
+    }
+
     fn install_template(&self, template_path: &str, target_name: &str) 
     -> Result<(), BlogError> {
         let mut target_path = self.path.clone();
@@ -279,7 +603,8 @@ impl Blog {
         }
     }
 
-    pub fn init(&mut self, core_data: CoreData, post: Option<String>, index: Option<String>) -> Result<(), BlogError> {
+    pub fn init(&mut self, core_data: CoreData, post: Option<String>, index: Option<String>,
+                tags: Option<String>, rss: Option<String>) -> Result<(), BlogError> {
         match OpenOptions::new().append(true).create(true).open(self.get_index_path()) {
         // match fs::File::create(self.get_index_path()) {
             Ok(_) => (),
@@ -287,7 +612,8 @@ impl Blog {
                 return Err(BlogError::InitWrite);
             }
         };
-        let templates = match AllTemplates::make_from_paths(post.clone(), index.clone()) {
+        let templates = match AllTemplates::make_from_paths(post.clone(), index.clone(),
+                                                            tags.clone(), rss.clone()) {
             Ok(t) => t,
             Err(e) => {
                 return Err(BlogError::InitTemplate(e));
@@ -302,6 +628,8 @@ impl Blog {
         };
         match &post { Some(s) => self.install_template(s, PATH_POST)?, _ => () };
         match &index { Some(s) => self.install_template(s, PATH_INDEX)?, _ => () };
+        if let Some(s) = &tags { self.install_template(s, PATH_TAGS)? };
+        if let Some(s) = &rss { self.install_template(s, PATH_RSS)? };
         match core_data.save() {
             Err(e) => {
                 return Err(BlogError::InitCoreData(e));
@@ -310,18 +638,123 @@ impl Blog {
         }
     }
 
+    /// Sync the blog with the posts. Changing the template of the posts,
+    /// of the table of contents, of the tag pages or of the RSS feed since
+    /// the last sync forces writing the posts, the table of contents, the
+    /// tag pages or the feeds again.
     pub fn sync(&mut self, force: bool) -> Result<usize, BlogError> {
         self.load()?;
-        let num_updated = self.update(false, force)?;
+        let stored_previews = self.previews.clone();
+        let stored_hashes = self.load_template_hashes().unwrap_or_default();
+        let post_changed = stored_hashes.post != self.templates.hashes.post;
+        let index_changed = stored_hashes.index != self.templates.hashes.index;
+        let tags_changed = stored_hashes.tags != self.templates.hashes.tags;
+        let rss_changed = stored_hashes.rss != self.templates.hashes.rss;
+        let num_updated = self.update(false, force || post_changed)?;
+        // front matter dates can reorder posts
+        self.index.sort_by_key(|post| post.first_published);
 
         if num_updated > 0 || force {
             self.write_toc()?;
-            self.write_rss()?;
+            self.write_feeds()?;
+            self.write_sitemap()?;
+            self.write_highlight_css()?;
+            self.write_tags()?;
+        } else {
+            if index_changed {
+                self.write_toc()?;
+            }
+            if tags_changed {
+                self.write_tags()?;
+            }
+            if rss_changed {
+                self.write_feeds()?;
+            }
+        }
+        if num_updated > 0 || force || post_changed || index_changed || tags_changed
+            || rss_changed || self.previews != stored_previews {
             self.persist()?;
         }  // else, no update necessary
         Ok(num_updated)
     }
 
+    /// Re-render only the posts in `post_dirs`, which changed since the
+    /// blog was synced, skipping those whose content stayed the same. The
+    /// table of contents and the tag pages are written again only if a post
+    /// was added or removed, or changed its title, date, tags or
+    /// description. The feeds carry the full articles and the sitemap their
+    /// dates of last update, so both are written again whenever a post in
+    /// the index is rendered. Returns the number of posts rendered.
+    pub fn sync_posts(&mut self, post_dirs: &[PathBuf]) -> Result<usize, BlogError> {
+        let now = SystemTime::now();
+        let variables = self.markdown_variables();
+        let mut relist = false;
+        let mut refeed = false;
+        let mut num_updated: usize = 0;
+        for dir in post_dirs {
+            // the post URL may be a slug, while the posts in the index
+            // all have their path after a sync
+            let in_index = self.index.iter().position(|post| post.path == *dir);
+            if !dir.join("index.md").is_file() {
+                self.remove_preview(&post_url_from_path(dir));
+                if let Some(i) = in_index {
+                    self.index.remove(i);
+                    relist = true;
+                }
+                continue;
+            }
+            let mut found = IndexedBlogPost::from(BlogPost{path: dir.clone(), last_updated: now});
+            found.read_front_matter()?;
+            if found.is_draft() || found.is_scheduled(now) {
+                let unchanged = self.preview_hash(&found.files_url()) == Some(&found.hash);
+                if let Some(i) = in_index {
+                    self.index.remove(i);
+                    relist = true;
+                } else if unchanged {
+                    // saved again without changes
+                    continue;
+                }
+                found.convert(&self.templates.post, &self.index_url, &variables)?;
+                self.set_preview(found.files_url(), found.hash.clone());
+            } else if let Some(i) = in_index {
+                self.remove_preview(&found.files_url());
+                if self.index[i].hash == found.hash {
+                    // saved again without changes
+                    continue;
+                }
+                let before = self.index[i].clone();
+                self.index[i].path = found.path;
+                self.index[i].hash = found.hash;
+                self.index[i].last_updated = now;
+                self.index[i].set_front_matter(found.meta);
+                self.index[i].convert(&self.templates.post, &self.index_url, &variables)?;
+                relist = relist || self.index[i].listed_differently(&before);
+                refeed = true;
+            } else {
+                self.remove_preview(&found.files_url());
+                found.first_published = found.meta.date
+                    .or(found.meta.publish_at)
+                    .unwrap_or(now);
+                found.convert(&self.templates.post, &self.index_url, &variables)?;
+                self.index.push(found);
+                relist = true;
+            }
+            num_updated += 1;
+        }
+
+        if relist {
+            self.index.sort_by_key(|post| post.first_published);
+            self.write_toc()?;
+            self.write_tags()?;
+        }
+        if relist || refeed {
+            self.write_feeds()?;
+            self.write_sitemap()?;
+        }
+        self.persist()?;
+        Ok(num_updated)
+    }
+
     fn persist(&self) -> Result<(), BlogError> {
         let mut writer = match WriterBuilder::new()
             .has_headers(false)
@@ -333,7 +766,7 @@ impl Blog {
             }
         };
         for post in self.index.iter() {
-            match writer.serialize(post) {
+            match writer.serialize(IndexEntry::from(post)) {
                 Ok(_) => (),
                 _ => {
                     return Err(BlogError::WriteIndexError(format!(
@@ -341,54 +774,279 @@ impl Blog {
                 }
             };
         }
+        let mut writer = match WriterBuilder::new()
+            .has_headers(false)
+            .from_path(self.get_template_hashes_path()) {
+            Ok(w) => w,
+            _ => {
+                return Err(BlogError::WriteIndexError(format!(
+                    "Failed to open template hashes file {:?}", &self.path)));
+            }
+        };
+        match writer.serialize(&self.templates.hashes) {
+            Ok(_) => (),
+            _ => {
+                return Err(BlogError::WriteIndexError(
+                    "Couldn't serialize the template hashes".to_string()));
+            }
+        };
+        let mut writer = match WriterBuilder::new()
+            .has_headers(false)
+            .from_path(self.get_previews_path()) {
+            Ok(w) => w,
+            _ => {
+                return Err(BlogError::WriteIndexError(format!(
+                    "Failed to open previews file {:?}", &self.path)));
+            }
+        };
+        for preview in self.previews.iter() {
+            match writer.serialize(preview) {
+                Ok(_) => (),
+                _ => {
+                    return Err(BlogError::WriteIndexError(format!(
+                        "Couldn't serialize {:?}", preview)));
+                }
+            };
+        }
         Ok(())
     }
 
     // Write table of contents HTML
-    fn render_index(&self) -> Result<String, BlogError> {
-        match self.templates.index.render("t1", &BlogRevIndex::new(&self.index)) {
+    fn render_index(&self, page: &BlogRevIndex) -> Result<String, BlogError> {
+        match self.templates.index.render("t1", page) {
             Ok(s) => Ok(s),
             Err(e) => Err(BlogError::WriteTocError(
                 format!("Couldn't render template: {:?}", e)))
         }
     }
 
+    /// write the first page of the table of contents to index.html
+    /// and the following ones to page/<n>/index.html
     fn write_toc(&self) -> Result<(), BlogError> {
-        match fs::write(self.get_toc_path(), self.render_index()?) {
+        let per_page = match CoreData::load() {
+            Ok(c) => c.posts_per_page(),
+            Err(e) => {return Err(BlogError::WriteTocError(
+                format!("Couldn't load core data: {}", e)));}
+        };
+        let pages = BlogRevIndex::paginate(&self.index, &self.index_url, per_page);
+        let pages_path = self.get_pages_path();
+        for page in pages.iter() {
+            let page_path = if page.page == 1 {
+                self.get_toc_path()
+            } else {
+                let mut page_dir = pages_path.clone();
+                page_dir.push(page.page.to_string());
+                if let Err(e) = fs::create_dir_all(&page_dir) {
+                    return Err(BlogError::WriteTocError(format!(
+                        "Couldn't create directory {:?}: {:?}", page_dir, e)));
+                }
+                page_dir.push("index.html");
+                page_dir
+            };
+            if let Err(e) = fs::write(page_path, self.render_index(page)?) {
+                return Err(BlogError::WriteTocError(format!(
+                    "Couldn't write to file: {:?}", e)));
+            }
+        }
+
+        // remove pages left over from when there were more posts per page
+        if pages.len() == 1 && !pages_path.exists() {
+            return Ok(());
+        }
+        for entry in Blog::list_entries(&pages_path, true)? {
+            let is_used = match entry.path.file_name()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<usize>().ok()) {
+                Some(n) => n > 1 && n <= pages.len(),
+                None => true
+            };
+            if !is_used {
+                let mut page_path = entry.path.clone(); page_path.push("index.html");
+                let _ = fs::remove_file(page_path);
+                let _ = fs::remove_dir(&entry.path);
+            }
+        }
+        Ok(())
+    }
+
+    // the newest posts, rendered for the feeds
+    fn feed_articles(&self, core_data: &CoreData)
+        -> Result<Vec<(&IndexedBlogPost, ParsedMarkdown)>, BlogError> {
+        let mut articles = vec![];
+        // feed readers need absolute links
+        let mut index_url = core_data.home().clone();
+        index_url.set_path(&self.index_url);
+        let mut variables = self.markdown_variables();
+        variables.insert("index_url".to_string(), index_url.to_string());
+        for post in self.index.iter().rev().take(FEED_LENGTH) {
+            let mut link_prefix = core_data.home().clone();
+            link_prefix.set_path(&post.files_url());
+            articles.push((post, post.render_article(link_prefix.to_string(), &variables)?));
+        }
+        Ok(articles)
+    }
+
+    // the RSS and Atom feeds are rendered from the same data
+    fn render_feed(template: &Handlebars, rss_data: &RssData) -> Result<String, String> {
+        match template.render("t1", rss_data) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(format!("Couldn't render template: {}", e))
+        }
+    }
+
+    fn render_rss(&self, rss_data: &RssData) -> Result<String, BlogError> {
+        Blog::render_feed(&self.templates.rss, rss_data).map_err(BlogError::WriteRssError)
+    }
+    
+    fn write_rss(&self, rss_data: &RssData) -> Result<(), BlogError> {
+        match fs::write(self.get_rss_path(), self.render_rss(rss_data)?) {
             Ok(_) => Ok(()),
-            Err(e) => Err(BlogError::WriteTocError(format!(
-                "Couldn't write to file: {:?}", e)))
+            Err(e) => Err(BlogError::WriteRssError(format!(
+                "Couldn't write to rss file: {:?}", e)))
         }
     }
 
-    fn to_rss_data(&self, core_data: CoreData) -> RssData {
-        let mut rss_data = RssData::new(core_data);
-        rss_data.push_posts(&self.index);
-        rss_data
-    }  
-    // TODO: refactor to avoid all of these unnecessary copies
+    fn render_atom(&self, rss_data: &RssData) -> Result<String, BlogError> {
+        Blog::render_feed(&self.templates.atom, rss_data).map_err(BlogError::WriteAtomError)
+    }
 
-    fn render_rss(&self) -> Result<String, BlogError> {
+    fn write_atom(&self, rss_data: &RssData) -> Result<(), BlogError> {
+        match fs::write(self.get_atom_path(), self.render_atom(rss_data)?) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(BlogError::WriteAtomError(format!(
+                "Couldn't write to atom file: {:?}", e)))
+        }
+    }
+
+    fn render_json_feed(feed: &JsonFeed) -> Result<String, BlogError> {
+        match serde_json::to_string_pretty(feed) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(BlogError::WriteJsonFeedError(
+                format!("Couldn't serialize feed: {}", e)))
+        }
+    }
+
+    fn write_json_feed(&self, feed: &JsonFeed) -> Result<(), BlogError> {
+        match fs::write(self.get_json_feed_path(), Blog::render_json_feed(feed)?) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(BlogError::WriteJsonFeedError(format!(
+                "Couldn't write to json feed file: {:?}", e)))
+        }
+    }
+
+    /// write the RSS, Atom and JSON feeds. The articles are rendered
+    /// once and shared between the three.
+    fn write_feeds(&self) -> Result<(), BlogError> {
         let core_data = match CoreData::load() {
             Ok(s) => s,
             Err(e) => {return Err(BlogError::WriteRssError(
                 format!("Couldn't load core data: {}", e)));}
         };
-        match self.templates.rss.render("t1", &self.to_rss_data(core_data)) {
+        let articles = self.feed_articles(&core_data)?;
+        let mut json_feed = JsonFeed::new(&core_data);
+        for (post, article) in articles.iter() {
+            json_feed.push_post(&core_data, post, article);
+        }
+        let mut rss_data = RssData::new(core_data);
+        for (post, article) in articles.iter() {
+            rss_data.push_post(post, article);
+        }
+        self.write_rss(&rss_data)?;
+        self.write_atom(&rss_data)?;
+        self.write_json_feed(&json_feed)
+    }
+
+    fn render_sitemap(&self) -> Result<String, BlogError> {
+        let core_data = match CoreData::load() {
+            Ok(s) => s,
+            Err(e) => {return Err(BlogError::WriteSitemapError(
+                format!("Couldn't load core data: {}", e)));}
+        };
+        let data = SitemapData::new(core_data.home(), &self.index_url, &self.index);
+        match self.templates.sitemap.render("t1", &data) {
             Ok(s) => Ok(s),
-            Err(e) => Err(BlogError::WriteRssError(
+            Err(e) => Err(BlogError::WriteSitemapError(
                 format!("Couldn't render template: {}", e)))
         }
     }
-    
-    fn write_rss(&self) -> Result<(), BlogError> {
-        match fs::write(self.get_rss_path(), self.render_rss()?) {
+
+    fn write_sitemap(&self) -> Result<(), BlogError> {
+        match fs::write(self.get_sitemap_path(), self.render_sitemap()?) {
             Ok(_) => Ok(()),
-            Err(e) => Err(BlogError::WriteRssError(format!(
-                "Couldn't write to rss file: {:?}", e)))
+            Err(e) => Err(BlogError::WriteSitemapError(format!(
+                "Couldn't write to sitemap file: {:?}", e)))
         }
     }
 
+    /// write the stylesheet for highlighted code, in the theme
+    /// chosen in the core data
+    fn write_highlight_css(&self) -> Result<(), BlogError> {
+        let core_data = match CoreData::load() {
+            Ok(s) => s,
+            Err(e) => {return Err(BlogError::WriteHighlightError(
+                format!("Couldn't load core data: {}", e)));}
+        };
+        let css = css_for_theme(core_data.highlight_theme())
+            .map_err(BlogError::WriteHighlightError)?;
+        match fs::write(self.get_highlight_path(), css) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(BlogError::WriteHighlightError(format!(
+                "Couldn't write to stylesheet: {:?}", e)))
+        }
+    }
+
+    fn render_tags(&self, data: &TagData) -> Result<String, BlogError> {
+        match self.templates.tags.render("t1", data) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(BlogError::WriteTagsError(
+                format!("Couldn't render template: {}", e)))
+        }
+    }
+
+    fn write_tag_page(dir: &PathBuf, rendered: String) -> Result<(), BlogError> {
+        if let Err(e) = fs::create_dir_all(dir) {
+            return Err(BlogError::WriteTagsError(format!(
+                "Couldn't create directory {:?}: {:?}", dir, e)));
+        }
+        let mut page_path = dir.clone(); page_path.push("index.html");
+        match fs::write(page_path, rendered) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(BlogError::WriteTagsError(format!(
+                "Couldn't write to file: {:?}", e)))
+        }
+    }
+
+    /// write the tag overview at tags/index.html and a page
+    /// for each tag at tags/<tag>/index.html
+    fn write_tags(&self) -> Result<(), BlogError> {
+        let tags_path = self.get_tags_path();
+        let tags = collect_tags(&self.index, &self.index_url);
+
+        let overview = TagData::overview(&tags, &self.index_url);
+        Blog::write_tag_page(&tags_path, self.render_tags(&overview)?)?;
+
+        for (i, tag) in tags.iter().enumerate() {
+            let mut tag_path = tags_path.clone(); tag_path.push(&tag.0.slug);
+            let data = TagData::for_tag(&tags, &self.index_url, i);
+            Blog::write_tag_page(&tag_path, self.render_tags(&data)?)?;
+        }
+
+        // remove the pages of tags that are no longer used.
+        // Directories with other content are left alone.
+        for entry in Blog::list_entries(&tags_path, true)? {
+            let is_used = match entry.path.file_name().and_then(|s| s.to_str()) {
+                Some(name) => tags.iter().any(|t| t.0.slug == name),
+                None => true
+            };
+            if !is_used {
+                let mut page_path = entry.path.clone(); page_path.push("index.html");
+                let _ = fs::remove_file(page_path);
+                let _ = fs::remove_dir(&entry.path);
+            }
+        }
+        Ok(())
+    }
+
     fn list_entries(path: &PathBuf, only_dir: bool) -> Result<Vec<BlogPost>, BlogError> {
         let mut posts: Vec<BlogPost> = vec![];
 
@@ -444,51 +1102,92 @@ impl Blog {
                 }
             }
         }
+        // directory listings come in no particular order
+        posts.sort_by(|a, b| a.path.cmp(&b.path));
         Ok(posts)
     }
 
     // perform a linear search in index
     // compare by relative path, in case the whole website moved location locally
     // TODO: replace with a more efficient method, when there are many posts
-    fn find_in_index(&self, post: &BlogPost) -> Option<usize> {
+    fn find_in_index(&self, post_url: &str) -> Option<usize> {
         for (i, b) in self.index.iter().enumerate() {
-            if b.post_url == post_url_from_path(&post.path) {
+            if b.post_url == post_url {
                 return Some(i);
             }
         }
         None
     }
 
+    /// the template variables available in the markdown of every post,
+    /// besides `post_url` and the front matter. The blog title is missing
+    /// if the blog hasn't been initialised.
+    fn markdown_variables(&self) -> Variables {
+        let mut variables = Variables::new();
+        variables.insert("index_url".to_string(), self.index_url.clone());
+        if let Ok(core_data) = CoreData::load() {
+            variables.insert("blog_title".to_string(), core_data.title().to_string());
+        }
+        variables
+    }
+
     fn update(&mut self, dry_run: bool, force: bool) -> Result<usize, BlogError> {
         let all_posts = self.list_posts()?;
+        let variables = self.markdown_variables();
+        let now = SystemTime::now();
         let mut num_updated: usize = 0;
+        // drafts and scheduled posts no longer found are forgotten
+        let mut previews = vec![];
         for post in all_posts {
-            if let Some(i) = self.find_in_index(&post) {
+            let mut found = IndexedBlogPost::from(post);
+            found.read_front_matter()?;
+            if found.is_draft() || found.is_scheduled(now) {
+                // render drafts and scheduled posts for previewing when they
+                // changed, but keep them out of the index. If the post was
+                // published before, it is unpublished below.
+                let files_url = found.files_url();
+                if ! dry_run && (force || self.preview_hash(&files_url) != Some(&found.hash)) {
+                    found.convert(&self.templates.post, &self.index_url, &variables)?;
+                }
+                previews.push(PreviewEntry{files_url, hash: found.hash});
+                continue;
+            }
+            // a slug given to a post already published changes its URL
+            let in_index = self.find_in_index(&found.post_url)
+                .or_else(|| self.find_in_index(&found.files_url()));
+            if let Some(i) = in_index {
                 self.index[i].checked = true;
-                self.index[i].path = post.path;  // populate path
-                let should_update = self.index[i].last_updated < post.last_updated;
+                self.index[i].path = found.path;  // populate path
+                self.index[i].set_front_matter(found.meta);
+                let should_update = self.index[i].hash != found.hash;
                 if should_update {
-                    self.index[i].last_updated = post.last_updated;
+                    // posts indexed before their content was hashed keep
+                    // their date of last update
+                    if !self.index[i].hash.is_empty() {
+                        self.index[i].last_updated = now;
+                    }
+                    self.index[i].hash = found.hash;
                     num_updated += 1;
                 }
                 if ! dry_run && (should_update || force) {
-                    self.index[i].convert(&self.templates.post, &self.index_url)?;
+                    self.index[i].convert(&self.templates.post, &self.index_url, &variables)?;
                 }
             } else {
-                let now = SystemTime::now();
-                let post_url = post_url_from_path(&post.path);
-                let mut new_post = IndexedBlogPost{
-                    path: post.path, last_updated: now,
-                    first_published: now, checked: true,
-                    title: None, post_url
-                };
+                found.last_updated = now;
+                found.first_published = found.meta.date
+                    .or(found.meta.publish_at)
+                    .unwrap_or(now);
+                found.checked = true;
                 if ! dry_run {
-                    new_post.convert(&self.templates.post, &self.index_url)?;
+                    found.convert(&self.templates.post, &self.index_url, &variables)?;
                 }
-                self.index.push(new_post);
+                self.index.push(found);
                 num_updated += 1;
             }
         }
+        if ! dry_run {
+            self.previews = previews;
+        }
         let old_index = self.index.clone(); 
         // TODO: avoid this unnecessary clone
 
@@ -510,11 +1209,12 @@ mod tests {
     use std::env;
     use std::fs;
     use std::path::PathBuf;
-    use std::time::{SystemTime, UNIX_EPOCH};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
     use handlebars::Handlebars;
 
     use templates::AllTemplates;
-    use super::{Blog, IndexedBlogPost, BlogPost};
+    use hash::content_hash;
+    use super::{Blog, BlogRevIndex, IndexedBlogPost, BlogPost, is_valid_slug};
 
     static POSTS: &[&'static str] = &["irkutsk", "krasnoyarsk", "yekaterinburg"];
 
@@ -573,22 +1273,26 @@ mod tests {
     fn can_update() {
         let mut blog = Blog::new(create_fake_dirs("blog2")).unwrap();
         let posts = blog.list_posts().expect("can't list posts");
-        blog.index = vec![
-            IndexedBlogPost::from(BlogPost{
-                path: posts[1].path.clone(),
-                last_updated: UNIX_EPOCH,
-            }),
-            IndexedBlogPost::from(BlogPost{
-                path: posts[0].path.clone(),
-                last_updated: posts[0].last_updated,
-            }),
-        ];
+        let mut changed = IndexedBlogPost::from(BlogPost{
+            path: posts[1].path.clone(),
+            last_updated: UNIX_EPOCH,
+        });
+        changed.hash = "0".to_string();
+        let mut unchanged = IndexedBlogPost::from(BlogPost{
+            path: posts[0].path.clone(),
+            last_updated: UNIX_EPOCH,
+        });
+        unchanged.hash = content_hash(&posts[0].path, "");
+        blog.index = vec![changed, unchanged];
         let num_updated;
         {
             num_updated = blog.update(true, false).expect("can't update");
         }
         cleanup(&blog.path);
         assert_eq!(num_updated, posts.len() - 1);
+        assert!(blog.index[0].last_updated > UNIX_EPOCH);
+        assert_eq!(blog.index[1].last_updated, UNIX_EPOCH);
+        assert_eq!(blog.index[0].hash, blog.index[1].hash);
         let expected_new_index_paths = vec![
             posts[1].path.clone(),
             posts[0].path.clone(),
@@ -601,6 +1305,131 @@ mod tests {
         assert_eq!(new_index_paths, expected_new_index_paths);
     }
 
+    #[test]
+    fn can_skip_drafts() {
+        let blog_path = create_fake_dirs("blog11");
+        let mut draft_path = blog_path.clone();
+        draft_path.push("_draft-novosibirsk");
+        fs::create_dir(&draft_path).expect("Should be able to create subdir!");
+        draft_path.push("index.md");
+        fs::File::create(&draft_path).expect("Should be able to create file!");
+        let mut published_draft = blog_path.clone();
+        published_draft.push(POSTS[0]);
+        published_draft.push("index.md");
+        fs::write(&published_draft, "---\ndraft: true\n---\n").expect("Can't write draft");
+
+        let mut blog = Blog::new(blog_path.clone()).unwrap();
+        let posts = blog.list_posts().expect("can't list posts");
+        assert_eq!(posts.len(), 4);
+        blog.index = vec![
+            IndexedBlogPost::from(BlogPost{
+                path: posts[1].path.clone(),
+                last_updated: UNIX_EPOCH,
+            }),
+            IndexedBlogPost::from(BlogPost{
+                path: posts[2].path.clone(),
+                last_updated: UNIX_EPOCH,
+            })
+        ];
+        let num_updated = blog.update(true, false).expect("can't update");
+        cleanup(&blog_path);
+        // irkutsk is unpublished, krasnoyarsk updated, yekaterinburg added
+        assert_eq!(num_updated, 3);
+        let post_urls = blog.index.iter()
+            .map(|x| x.post_url.clone())
+            .collect::<Vec<String>>();
+        assert_eq!(post_urls, vec!["/blog11/krasnoyarsk/", "/blog11/yekaterinburg/"]);
+    }
+
+    #[test]
+    fn can_schedule_posts() {
+        let blog_path = create_fake_dirs("blog12");
+        let mut scheduled = blog_path.clone();
+        scheduled.push(POSTS[0]);
+        scheduled.push("index.md");
+        fs::write(&scheduled, "---\npublish_at: 2999-01-01\n---\n").expect("Can't write post");
+        let mut released = blog_path.clone();
+        released.push(POSTS[1]);
+        released.push("index.md");
+        fs::write(&released, "---\npublish_at: 1970-01-02\n---\n").expect("Can't write post");
+
+        let mut blog = Blog::new(blog_path.clone()).unwrap();
+        let num_updated = blog.update(true, false).expect("can't update");
+        cleanup(&blog_path);
+        assert_eq!(num_updated, 2);
+        let post_urls = blog.index.iter()
+            .map(|x| x.post_url.clone())
+            .collect::<Vec<String>>();
+        assert_eq!(post_urls, vec!["/blog12/krasnoyarsk/", "/blog12/yekaterinburg/"]);
+        assert_eq!(blog.index[0].first_published, UNIX_EPOCH + Duration::from_secs(86400));
+    }
+
+    #[test]
+    fn can_publish_under_slug() {
+        let blog_path = create_fake_dirs("blog14");
+        let post = blog_path.join(POSTS[0]);
+        fs::write(post.join("index.md"), "---\nslug: lake-baikal\n---\n![A lake](lake.png)\n")
+            .expect("Can't write post");
+        let mut blog = Blog::new(blog_path.clone()).unwrap();
+        blog.update(false, false).expect("can't update");
+        let html = fs::read_to_string(blog_path.join("lake-baikal/index.html"));
+        let in_post_dir = post.join("index.html").exists();
+        let pages = BlogRevIndex::paginate(&blog.index, &blog.index_url, None);
+        let toc = blog.render_index(&pages[0]).expect("can't render toc");
+        cleanup(&blog_path);
+        assert!(html.expect("not written under the slug").contains("src=\"/blog14/irkutsk/lake.png\""));
+        assert!(!in_post_dir);
+        assert_eq!(blog.index[0].post_url, "/blog14/lake-baikal/");
+        assert!(toc.contains("href=\"/blog14/lake-baikal/\""));
+
+        assert!(is_valid_slug("lake-baikal"));
+        assert!(!is_valid_slug("tags"));
+        assert!(!is_valid_slug("../elsewhere"));
+        assert!(!is_valid_slug(""));
+    }
+
+    #[test]
+    fn can_render_previews_once() {
+        let blog_path = create_fake_dirs("blog15");
+        let draft = blog_path.join(POSTS[0]);
+        fs::write(draft.join("index.md"), "---\ndraft: true\n---\nFirst\n")
+            .expect("Can't write draft");
+        let preview = draft.join("index.html");
+        let mut blog = Blog::new(blog_path.clone()).unwrap();
+        blog.update(false, false).expect("can't update");
+        let rendered = preview.exists();
+        fs::remove_file(&preview).expect("Can't remove preview");
+        blog.update(false, false).expect("can't update");
+        let rendered_unchanged = preview.exists();
+        blog.update(false, true).expect("can't update");
+        let rendered_forced = preview.exists();
+        fs::remove_file(&preview).expect("Can't remove preview");
+        fs::write(draft.join("index.md"), "---\ndraft: true\n---\nSecond\n")
+            .expect("Can't write draft");
+        blog.update(false, false).expect("can't update");
+        let rendered_changed = preview.exists();
+        blog.persist().expect("can't persist");
+        let previews = blog.load_previews();
+        cleanup(&blog_path);
+        assert!(rendered);
+        assert!(!rendered_unchanged);
+        assert!(rendered_forced);
+        assert!(rendered_changed);
+        assert_eq!(previews, blog.previews);
+        assert_eq!(previews.len(), 1);
+        assert_eq!(previews[0].files_url, "/blog15/irkutsk/");
+    }
+
+    #[test]
+    fn can_persist_template_hashes() {
+        let blog = Blog::new(create_fake_dirs("blog13")).unwrap();
+        assert_eq!(blog.load_template_hashes(), None);
+        blog.persist().expect("can't persist");
+        let hashes = blog.load_template_hashes();
+        cleanup(&blog.path);
+        assert_eq!(hashes, Some(blog.templates.hashes.clone()));
+    }
+
     #[test]
     fn can_compute_input_output_filename() {
         let blogpost = IndexedBlogPost::from(BlogPost{
@@ -657,9 +1486,35 @@ mod tests {
         let mut template = Handlebars::new();
         template.register_template_string("t1", "{{#each index}}{{title}}{{/each}}").unwrap();
         // let template = "{{#each index}}{{title}}{{/each}}";
-        blog.set_templates(AllTemplates::from((Handlebars::new(), template, Handlebars::new())));
-        let rendered = blog.render_index().expect("Couldn't render");
+        blog.set_templates(AllTemplates::from((Handlebars::new(), template, Handlebars::new(),
+                                               Handlebars::new(), Handlebars::new(),
+                                               Handlebars::new())));
+        let pages = BlogRevIndex::paginate(&blog.index, &blog.index_url, None);
+        let rendered = blog.render_index(&pages[0]).expect("Couldn't render");
         assert_eq!(rendered, format!("{}", title));
         cleanup(&blog_path);
     }
+
+    #[test]
+    fn can_paginate() {
+        let mut index = vec![];
+        for i in 0..5 {
+            let mut post = IndexedBlogPost::example();
+            post.post_url = format!("/blog/post-{}/", i);
+            index.push(post);
+        }
+        let pages = BlogRevIndex::paginate(&index, "/blog/", Some(2));
+        assert_eq!(pages.len(), 3);
+        let urls: Vec<&str> = pages[0].index.iter().map(|p| p.post_url.as_str()).collect();
+        assert_eq!(urls, vec!["/blog/post-4/", "/blog/post-3/"]);
+        assert_eq!(pages[0].prev_url, None);
+        assert_eq!(pages[0].next_url, Some("/blog/page/2/".to_string()));
+        assert_eq!(pages[1].prev_url, Some("/blog/".to_string()));
+        assert_eq!(pages[2].index.len(), 1);
+        assert_eq!(pages[2].total_pages, 3);
+        assert_eq!(pages[2].next_url, None);
+
+        assert_eq!(BlogRevIndex::paginate(&index, "/blog/", None).len(), 1);
+        assert_eq!(BlogRevIndex::paginate(&[], "/blog/", Some(2))[0].total_pages, 1);
+    }
 }
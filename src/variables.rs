@@ -0,0 +1,181 @@
+use pulldown_cmark::Event;
+use serde_json::{self, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use parser::SidenoteParser;
+use front_matter::FrontMatter;
+use sidenote_error::SidenoteError;
+
+
+/// the template variables available in the markdown, like `post_url`
+pub type Variables = HashMap<String, String>;
+
+
+/// add the front matter to the variables as `meta.<field>`. Lists like
+/// the tags are joined with commas, and dates are left out.
+pub fn add_front_matter(variables: &mut Variables, front_matter: &FrontMatter) {
+    let meta = match serde_json::to_value(front_matter) {
+        Ok(Value::Object(m)) => m,
+        _ => { return; }
+    };
+    for (field, value) in meta {
+        let value = match value {
+            Value::String(s) => s,
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Array(values) => values.iter()
+                .filter_map(|v| v.as_str())
+                .collect::<Vec<&str>>()
+                .join(", "),
+            _ => continue
+        };
+        variables.insert(format!("meta.{}", field), value);
+    }
+}
+
+
+/// replace every `{{name}}` in `text` with its value. Fails with the name
+/// of the first variable that doesn't exist. As in Handlebars, `\{{name}}`
+/// is left as `{{name}}`.
+pub fn substitute(text: &str, variables: &Variables) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        if rest[..start].ends_with('\\') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str("{{");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let end = match rest[start..].find("}}") {
+            Some(i) => start + i,
+            None => break
+        };
+        let name = rest[start + 2..end].trim();
+        match variables.get(name) {
+            Some(value) => {
+                result.push_str(&rest[..start]);
+                result.push_str(value);
+            },
+            None => { return Err(name.to_string()); }
+        }
+        rest = &rest[end + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+
+/// Template variables, `{{post_url}}`, in the text, in HTML and in links.
+/// Their double braces can't be a sidenote, as sidenotes can't be nested.
+/// Code is left as it is.
+impl<'a> SidenoteParser<'a> {
+
+    /// `remaining_text` starts with the second brace of the variable,
+    /// the first being at `offset`. The markdown parser splits text at
+    /// underscores, so the variable may continue in the next text events.
+    pub fn parse_variable(&mut self, offset: usize) -> Result<Event<'a>, SidenoteError> {
+        let mut variable = self.remaining_text.clone();
+        while !variable.contains("}}") {
+            match self.next_raw_event() {
+                Some((Event::Text(_), start, end)) => {
+                    variable.push_str(self.raw(start, end));
+                    self.set_span(start, end);
+                },
+                _ => {
+                    return Err(SidenoteError::NotMatched(self.source_position(offset)));
+                }
+            }
+        }
+        let end = variable.find("}}").unwrap();
+        let name = variable[1..end].trim().to_string();
+        self.remaining_text = variable[end + 2..].to_string();
+        match self.variables.get(&name) {
+            Some(value) => Ok(Event::Text(Cow::from(value.clone()))),
+            None => Err(SidenoteError::Variable(name, self.source_position(offset)))
+        }
+    }
+
+    /// called on an escaped brace: `\{{name}}` is kept as `{{name}}`. The
+    /// markdown parser gives the escaped brace an event of its own, so read
+    /// ahead for the rest. Anything else is just a brace.
+    pub fn parse_escaped_variable(&mut self) -> Event<'a> {
+        let span = self.span;
+        let mut variable = String::new();
+        let mut read_ahead = vec![];
+        while self.remaining_text.is_empty() {
+            let (event, start, end) = match self.next_raw_event() {
+                Some(e) => e,
+                None => break
+            };
+            let raw = self.raw(start, end);
+            let unescaped = match event {
+                Event::Text(ref text) => raw == text,
+                _ => false
+            };
+            read_ahead.push((event, start, end));
+            if !unescaped || (variable.is_empty() && !raw.starts_with('{')) {
+                break;
+            }
+            variable.push_str(raw);
+            self.set_span(start, end);
+            if let Some(end) = variable.find("}}") {
+                // the rest of the text isn't escaped
+                self.escaped = false;
+                self.remaining_text = variable[end + 2..].to_string();
+                return Event::Text(Cow::from(format!("{{{}", &variable[..end + 2])));
+            }
+        }
+        read_ahead.reverse();
+        self.lookahead.extend(read_ahead);
+        self.span = span;
+        Event::Text(Cow::from("{"))
+    }
+
+    /// substitute the variables in the HTML or link of the current event
+    pub fn substitute_variables(&self, text: Cow<'a, str>) -> Result<Cow<'a, str>, SidenoteError> {
+        if !text.contains("{{") {
+            return Ok(text);
+        }
+        match substitute(&text, &self.variables) {
+            Ok(s) => Ok(Cow::from(s)),
+            Err(name) => Err(SidenoteError::Variable(name, self.source_position(self.span.0)))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{substitute, add_front_matter, Variables};
+    use front_matter::FrontMatter;
+
+    #[test]
+    fn can_substitute() {
+        let mut variables = Variables::new();
+        variables.insert("post_url".to_string(), "/blog/post/".to_string());
+        assert_eq!(substitute("<iframe src=\"{{ post_url }}demo.html\">", &variables),
+                   Ok("<iframe src=\"/blog/post/demo.html\">".to_string()));
+        assert_eq!(substitute("{{post_url}}{{post_url}} {{", &variables),
+                   Ok("/blog/post//blog/post/ {{".to_string()));
+        assert_eq!(substitute("{{index_url}}", &variables), Err("index_url".to_string()));
+        assert_eq!(substitute(r"<script>\{{index_url}} {{post_url}}</script>", &variables),
+                   Ok("<script>{{index_url}} /blog/post/</script>".to_string()));
+    }
+
+    #[test]
+    fn can_add_front_matter() {
+        let front_matter = FrontMatter{
+            tags: vec!["clogs".to_string(), "shoes".to_string()],
+            slug: Some("clogs".to_string()),
+            ..FrontMatter::default()
+        };
+        let mut variables = Variables::new();
+        add_front_matter(&mut variables, &front_matter);
+        assert_eq!(variables["meta.tags"], "clogs, shoes");
+        assert_eq!(variables["meta.slug"], "clogs");
+        assert_eq!(variables["meta.draft"], "false");
+        assert!(!variables.contains_key("meta.title"));
+    }
+}
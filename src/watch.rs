@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use toc::{Blog, PATH_PREVIEWS};
+use templates::PATH_TEMPLATE_HASHES;
+
+
+/// how often to look for changes in the blog
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+
+type Sources = BTreeMap<PathBuf, SystemTime>;
+
+
+fn list_sources(dir: &Path, in_post: bool, sources: &mut Sources) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => { return; }
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if path.is_dir() {
+            // the tag pages and further pages of the table of contents
+            // are written by `sync`
+            if in_post || !(name == "tags" || name == "page" || name.starts_with('.')) {
+                list_sources(&path, true, sources);
+            }
+        } else if (in_post && name != "index.html") || (!in_post && name.starts_with('.')
+                                                          && name != ".index.csv"
+                                                          && name != PATH_TEMPLATE_HASHES
+                                                          && name != PATH_PREVIEWS) {
+            if let Ok(modified) = entry.metadata().and_then(|m| m.modified()) {
+                sources.insert(path, modified);
+            }
+        }
+    }
+}
+
+
+/// the files `sync` reads, with the time they were last modified: the
+/// posts, with their images and other files, and the templates. The files
+/// `sync` writes are left out.
+fn sources(blog_dir: &Path) -> Sources {
+    let mut sources = Sources::new();
+    list_sources(blog_dir, false, &mut sources);
+    sources
+}
+
+
+/// what changed between two listings of the sources
+#[derive(Debug, PartialEq)]
+enum Changes {
+    Nothing,
+    /// the templates or the blog settings changed, so the blog needs
+    /// loading again
+    Everything,
+    Posts(Vec<PathBuf>)
+}
+
+
+fn changes(blog_dir: &Path, before: &Sources, after: &Sources) -> Changes {
+    let mut posts: Vec<PathBuf> = vec![];
+    let removed = before.keys().filter(|path| !after.contains_key(*path));
+    let changed = after.iter()
+        .filter(|&(path, modified)| before.get(path) != Some(modified))
+        .map(|(path, _)| path);
+    for path in removed.chain(changed) {
+        let post = match path.strip_prefix(blog_dir).ok().and_then(|p| p.components().next()) {
+            Some(name) => blog_dir.join(name.as_os_str()),
+            None => continue
+        };
+        // a file in the blog directory itself
+        if post == *path {
+            return Changes::Everything;
+        }
+        if !posts.contains(&post) {
+            posts.push(post);
+        }
+    }
+    if posts.is_empty() {
+        Changes::Nothing
+    } else {
+        Changes::Posts(posts)
+    }
+}
+
+
+/// Keeps the blog in memory to sync only what changed: the posts whose
+/// markdown or other files changed are rendered again, and all of them
+/// only when a template changes.
+pub struct Watcher {
+    blog_dir: PathBuf,
+    blog: Option<Blog>,
+    sources: Sources
+}
+
+
+impl Watcher {
+
+    /// sync the blog at `blog_dir` as it is now
+    pub fn new(blog_dir: PathBuf) -> Self {
+        let sources = sources(&blog_dir);
+        let mut watcher = Watcher{blog_dir, blog: None, sources};
+        watcher.sync_everything();
+        watcher
+    }
+
+    // loads the blog again, with the templates. Like `sync`, this renders
+    // only the posts that changed, and everything a changed template is
+    // used for.
+    fn sync_everything(&mut self) -> bool {
+        self.blog = None;
+        let mut blog = match Blog::new(self.blog_dir.clone()) {
+            Ok(b) => b,
+            Err(e) => {
+                println!("{}", e);
+                return false;
+            }
+        };
+        match blog.sync(false) {
+            Ok(i) => {
+                println!("Updated {} posts", i);
+                self.blog = Some(blog);
+                true
+            },
+            Err(err) => {
+                println!("Couldn't sync: {}", err);
+                false
+            }
+        }
+    }
+
+    fn sync_posts(&mut self, posts: &[PathBuf]) -> bool {
+        let result = match self.blog {
+            Some(ref mut blog) => blog.sync_posts(posts),
+            None => {
+                return self.sync_everything();
+            }
+        };
+        match result {
+            Ok(i) => {
+                println!("Rendered {} posts", i);
+                true
+            },
+            Err(err) => {
+                // start over on the next change, in case the blog is only
+                // partly updated
+                self.blog = None;
+                println!("Couldn't sync: {}", err);
+                false
+            }
+        }
+    }
+
+    /// sync whatever changed since the last call. Returns `None` if nothing
+    /// changed, otherwise whether syncing succeeded.
+    pub fn sync_changes(&mut self) -> Option<bool> {
+        let current = sources(&self.blog_dir);
+        let changes = changes(&self.blog_dir, &self.sources, &current);
+        self.sources = current;
+        match changes {
+            Changes::Nothing => None,
+            Changes::Everything => Some(self.sync_everything()),
+            Changes::Posts(posts) => Some(self.sync_posts(&posts))
+        }
+    }
+}
+
+
+/// Watch the blog at `blog_dir`, syncing whatever changes.
+pub fn watch(blog_dir: PathBuf) {
+    let mut watcher = Watcher::new(blog_dir);
+    println!("Watching for changes");
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        watcher.sync_changes();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::{sources, changes, Changes, Sources};
+
+    #[test]
+    fn can_list_sources() {
+        let mut root = env::temp_dir();
+        root.push("wellington-watch");
+        let _ = fs::remove_dir_all(&root);
+        for dir in &["blog/post-1/images", "blog/tags/rust"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in &["blog/index.html", "blog/.post_template.html", "blog/.index.csv",
+                      "blog/.templates.csv", "blog/rss.xml", "blog/post-1/index.md", "blog/post-1/index.html",
+                      "blog/post-1/images/a.png", "blog/tags/rust/index.html"] {
+            fs::write(root.join(file), "").unwrap();
+        }
+        let files: Vec<PathBuf> = sources(&root.join("blog")).into_iter().map(|s| s.0).collect();
+        assert_eq!(files, vec![
+            root.join("blog/.post_template.html"),
+            root.join("blog/post-1/images/a.png"),
+            root.join("blog/post-1/index.md"),
+        ]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn can_find_changes() {
+        let blog = PathBuf::from("/site/blog");
+        let time = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+        let before: Sources = vec![
+            (blog.join(".post_template.html"), time(1)),
+            (blog.join("post-1/index.md"), time(1)),
+            (blog.join("post-1/images/a.png"), time(1)),
+            (blog.join("post-2/index.md"), time(1)),
+        ].into_iter().collect();
+        assert_eq!(changes(&blog, &before, &before), Changes::Nothing);
+
+        let mut after = before.clone();
+        after.insert(blog.join("post-1/images/a.png"), time(2));
+        after.remove(&blog.join("post-2/index.md"));
+        after.insert(blog.join("post-3/index.md"), time(2));
+        assert_eq!(changes(&blog, &before, &after), Changes::Posts(vec![
+            blog.join("post-2"), blog.join("post-1"), blog.join("post-3")]));
+
+        after.insert(blog.join(".post_template.html"), time(2));
+        assert_eq!(changes(&blog, &before, &after), Changes::Everything);
+    }
+}